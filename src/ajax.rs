@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+use crate::events::{self, EventKind};
+use crate::relay::{Pulse, PulseOutput, RegisterForStatus, RelayActor, RelayStatus, UnregisterForStatus};
+use crate::rules::InputState;
+
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_retries() -> u32 {
+    1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HubStateKind {
+    Armed,
+    Disarmed,
+    NightMode,
+    Alarm,
+    Tamper,
+    Fault,
+    PowerFailure,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HubStateMapping {
+    pub state: HubStateKind,
+    #[serde(default)]
+    pub group: Option<u32>,
+    pub inputs: Vec<InputState>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct HubProfile {
+    #[serde(default)]
+    pub description: String,
+    pub states: Vec<HubStateMapping>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HubCommand {
+    pub output: usize,
+    #[serde(flatten)]
+    pub pulse: Pulse,
+    pub expect: Vec<InputState>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Pulses again when the hub did not react at all. Not safe when the output is wired to
+    /// a toggle input, where a pulse the hub saw late would be undone by the retry; use 0 there.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct AjaxConfig {
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, HubProfile>,
+    #[serde(default)]
+    pub commands: HashMap<String, HubCommand>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HubState {
+    pub state: HubStateKind,
+    pub group: Option<u32>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct HubStatus {
+    pub profile: String,
+    pub states: Vec<HubState>,
+    pub label: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Clone, Serialize)]
+pub struct HubCommandResult {
+    pub command: String,
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum HubCommandError {
+    UnknownCommand,
+    Busy { command: String },
+    Output { message: String },
+    Timeout { attempts: u32, elapsed_ms: u64 },
+    Cancelled,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<HubStatus>")]
+pub struct GetHubStatus;
+
+#[derive(Message)]
+#[rtype(result = "Result<HubCommandResult, HubCommandError>")]
+pub struct RunHubCommand {
+    pub name: String,
+}
+
+struct PendingCommand {
+    name: String,
+    command: HubCommand,
+    attempts: u32,
+    started: Instant,
+    changed: bool,
+    timeout_handle: Option<SpawnHandle>,
+    sender: oneshot::Sender<Result<HubCommandResult, HubCommandError>>,
+}
+
+pub struct AjaxActor {
+    id: usize,
+    profile_name: String,
+    profile: Option<HubProfile>,
+    commands: HashMap<String, HubCommand>,
+    status: Option<HubStatus>,
+    inputs: Vec<u32>,
+    pending: Option<PendingCommand>,
+}
+
+impl Default for AjaxActor {
+    fn default() -> Self {
+        AjaxActor {
+            id: 0,
+            profile_name: String::new(),
+            profile: None,
+            commands: HashMap::new(),
+            status: None,
+            inputs: Vec::new(),
+            pending: None,
+        }
+    }
+}
+
+impl Actor for AjaxActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        RelayActor::from_registry().send(RegisterForStatus(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+
+        Running::Stop
+    }
+}
+
+impl Supervised for AjaxActor {}
+
+impl SystemService for AjaxActor {}
+
+impl AjaxConfig {
+    /// Checks that every profile mapping and command only refers to inputs the relay has.
+    pub fn validate(&self, inputs_number: usize) -> Result<(), String> {
+        // Without expected inputs a command would count as confirmed before the hub saw the pulse.
+        if let Some((name, _)) = self.commands.iter().find(|(_, command)| command.expect.is_empty()) {
+            return Err(format!("ajax {} expects no inputs", name));
+        }
+
+        let mappings = self.profiles.iter()
+            .flat_map(|(name, profile)| profile.states.iter()
+                .flat_map(move |mapping| mapping.inputs.iter().map(move |input| (name, input.input))));
+        let commands = self.commands.iter()
+            .flat_map(|(name, command)| command.expect.iter().map(move |input| (name, input.input)));
+
+        match mappings.chain(commands).find(|&(_, input)| input == 0 || input > inputs_number) {
+            Some((name, input)) => Err(format!("ajax {} refers to invalid input {}", name, input)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl HubStateKind {
+    fn label(self) -> &'static str {
+        match self {
+            HubStateKind::Armed => "Armed",
+            HubStateKind::Disarmed => "Disarmed",
+            HubStateKind::NightMode => "Night mode",
+            HubStateKind::Alarm => "Alarm",
+            HubStateKind::Tamper => "Tamper",
+            HubStateKind::Fault => "Fault",
+            HubStateKind::PowerFailure => "Power failure",
+        }
+    }
+}
+
+impl HubProfile {
+    pub fn decode(&self, inputs: &[u32]) -> Vec<HubState> {
+        self.states.iter()
+            .filter(|mapping| mapping.inputs.iter()
+                .all(|input| inputs.get(input.input.wrapping_sub(1)) == Some(&input.state)))
+            .map(|mapping| HubState {
+                state: mapping.state,
+                group: mapping.group,
+            })
+            .collect()
+    }
+}
+
+pub fn hub_label(states: &[HubState]) -> String {
+    if states.is_empty() {
+        return "Ajax: Unknown".to_string();
+    }
+
+    let states = states.iter()
+        .map(|state| match state.group {
+            Some(group) => format!("{} in group {}", state.state.label(), group),
+            None => state.state.label().to_string(),
+        })
+        .collect::<Vec<String>>();
+
+    format!("Ajax: {}", states.join(" / "))
+}
+
+impl AjaxActor {
+    pub fn new(config: AjaxConfig) -> Self {
+        let profile_name = config.profile.unwrap_or_default();
+        let profile = config.profiles.get(&profile_name).cloned();
+
+        if !profile_name.is_empty() && profile.is_none() {
+            println!("Ajax profile {} is not defined", profile_name);
+        }
+
+        Self {
+            profile_name,
+            profile,
+            commands: config.commands,
+            ..AjaxActor::default()
+        }
+    }
+
+    fn send_pulse(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let (output, pulse, timeout) = match self.pending {
+            Some(ref mut pending) => {
+                pending.attempts += 1;
+
+                println!("Hub command {}: pulse output {} (attempt {})", pending.name, pending.command.output, pending.attempts);
+
+                (pending.command.output, pending.command.pulse.clone(), Duration::from_millis(pending.command.timeout_ms))
+            }
+            None => return,
+        };
+
+        RelayActor::from_registry().send(PulseOutput { number: output, pulse })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                let message = match res {
+                    Ok(Ok(_)) => None,
+                    Ok(Err(err)) => Some(format!("{:?}", err)),
+                    Err(err) => Some(err.to_string()),
+                };
+
+                if let Some(message) = message {
+                    act.complete(ctx, Err(HubCommandError::Output { message }));
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        let handle = ctx.run_later(timeout, |act, ctx| act.timeout(ctx));
+
+        if let Some(ref mut pending) = self.pending {
+            pending.timeout_handle = Some(handle);
+        }
+    }
+
+    fn timeout(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let (attempts, retries, changed, elapsed_ms) = match self.pending {
+            Some(ref mut pending) => {
+                pending.timeout_handle = None;
+
+                (pending.attempts, pending.command.retries, pending.changed, pending.started.elapsed().as_millis() as u64)
+            }
+            None => return,
+        };
+
+        // If the hub reacted but did not reach the expected state, another pulse could undo it.
+        if attempts <= retries && !changed {
+            self.send_pulse(ctx);
+        } else {
+            self.complete(ctx, Err(HubCommandError::Timeout { attempts, elapsed_ms }));
+        }
+    }
+
+    fn complete(&mut self, ctx: &mut <Self as Actor>::Context, result: Result<HubCommandResult, HubCommandError>) {
+        if let Some(mut pending) = self.pending.take() {
+            if let Some(handle) = pending.timeout_handle.take() {
+                ctx.cancel_future(handle);
+            }
+
+
+            println!("Hub command {}: {}", pending.name, if result.is_ok() { "confirmed" } else { "failed" });
+
+            if pending.sender.send(result).is_err() {
+                println!("Hub command {} result was not delivered", pending.name);
+            }
+        }
+    }
+
+    fn check_pending(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let confirmed = self.pending.as_ref().map_or(false, |pending| pending.command.expect.iter()
+            .all(|input| self.inputs.get(input.input.wrapping_sub(1)) == Some(&input.state)));
+
+        if !confirmed {
+            return;
+        }
+
+        let result = self.pending.as_ref().map(|pending| HubCommandResult {
+            command: pending.name.clone(),
+            attempts: pending.attempts,
+            elapsed_ms: pending.started.elapsed().as_millis() as u64,
+        });
+
+        if let Some(result) = result {
+            self.complete(ctx, Ok(result));
+        }
+    }
+}
+
+impl Handler<RelayStatus> for AjaxActor {
+    type Result = ();
+
+    fn handle(&mut self, message: RelayStatus, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(inputs) = message.inputs {
+            let previous = std::mem::replace(&mut self.inputs, inputs);
+
+            if let Some(ref mut pending) = self.pending {
+                let inputs = &self.inputs;
+
+                pending.changed |= pending.command.expect.iter()
+                    .any(|input| previous.get(input.input.wrapping_sub(1)) != inputs.get(input.input.wrapping_sub(1)));
+            }
+
+            self.check_pending(ctx);
+        } else {
+            return;
+        }
+
+        let profile = match self.profile {
+            Some(ref profile) => profile,
+            None => return,
+        };
+
+        let states = profile.decode(&self.inputs);
+
+        if self.status.as_ref().map_or(false, |status| status.states == states) {
+            return;
+        }
+
+        let label = hub_label(&states);
+
+        println!("{}", label);
+
+        let status = HubStatus {
+            profile: self.profile_name.clone(),
+            states,
+            label,
+            changed_at: Local::now().naive_local(),
+        };
+
+        self.status = Some(status.clone());
+        events::publish(EventKind::HubStateChanged { hub: status });
+    }
+}
+
+impl Handler<GetHubStatus> for AjaxActor {
+    type Result = MessageResult<GetHubStatus>;
+
+    fn handle(&mut self, _: GetHubStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.status.clone())
+    }
+}
+
+impl Handler<RunHubCommand> for AjaxActor {
+    type Result = ResponseFuture<Result<HubCommandResult, HubCommandError>>;
+
+    fn handle(&mut self, message: RunHubCommand, ctx: &mut Context<Self>) -> Self::Result {
+        let command = match self.commands.get(&message.name) {
+            Some(command) => command.clone(),
+            None => return Box::pin(async { Err(HubCommandError::UnknownCommand) }),
+        };
+
+        if let Some(ref pending) = self.pending {
+            let command = pending.name.clone();
+
+            return Box::pin(async { Err(HubCommandError::Busy { command }) });
+        }
+
+        let (sender, receiver) = oneshot::channel();
+
+        self.pending = Some(PendingCommand {
+            name: message.name,
+            command,
+            attempts: 0,
+            started: Instant::now(),
+            changed: false,
+            timeout_handle: None,
+            sender,
+        });
+
+        self.check_pending(ctx);
+        self.send_pulse(ctx);
+
+        Box::pin(async move {
+            receiver.await.unwrap_or(Err(HubCommandError::Cancelled))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(state: HubStateKind, group: Option<u32>, inputs: &[(usize, u32)]) -> HubStateMapping {
+        HubStateMapping {
+            state,
+            group,
+            inputs: inputs.iter().map(|&(input, state)| InputState { input, state }).collect(),
+        }
+    }
+
+    fn profile() -> HubProfile {
+        HubProfile {
+            description: String::new(),
+            states: vec![
+                mapping(HubStateKind::Armed, Some(1), &[(1, 1), (2, 0)]),
+                mapping(HubStateKind::NightMode, Some(1), &[(1, 1), (2, 1)]),
+                mapping(HubStateKind::Disarmed, Some(1), &[(1, 0)]),
+                mapping(HubStateKind::Alarm, None, &[(3, 1)]),
+            ],
+        }
+    }
+
+    #[test]
+    fn decode_matches_all_inputs_of_a_mapping() {
+        let states = profile().decode(&[1, 0, 1]);
+
+        assert_eq!(states, vec![
+            HubState { state: HubStateKind::Armed, group: Some(1) },
+            HubState { state: HubStateKind::Alarm, group: None },
+        ]);
+    }
+
+    #[test]
+    fn decode_ignores_missing_and_zero_inputs() {
+        let mut profile = profile();
+
+        profile.states.push(mapping(HubStateKind::Fault, None, &[(0, 0)]));
+        profile.states.push(mapping(HubStateKind::Tamper, None, &[(4, 0)]));
+
+        assert_eq!(profile.decode(&[0, 0, 0]), vec![HubState { state: HubStateKind::Disarmed, group: Some(1) }]);
+        assert!(profile.decode(&[]).is_empty());
+    }
+
+    #[test]
+    fn hub_label_joins_states() {
+        let states = vec![
+            HubState { state: HubStateKind::NightMode, group: Some(2) },
+            HubState { state: HubStateKind::PowerFailure, group: None },
+        ];
+
+        assert_eq!(hub_label(&states), "Ajax: Night mode in group 2 / Power failure");
+    }
+
+    #[test]
+    fn hub_label_without_states_is_unknown() {
+        assert_eq!(hub_label(&[]), "Ajax: Unknown");
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_inputs() {
+        let mut config = AjaxConfig::default();
+
+        config.profiles.insert("hub".to_string(), profile());
+
+        assert!(config.validate(3).is_ok());
+        assert!(config.validate(2).is_err());
+
+        config.profiles.insert("zero".to_string(), HubProfile {
+            description: String::new(),
+            states: vec![mapping(HubStateKind::Fault, None, &[(0, 1)])],
+        });
+
+        assert!(config.validate(3).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_commands_without_expected_inputs() {
+        let mut config = AjaxConfig::default();
+        let mut command = HubCommand {
+            output: 1,
+            pulse: Pulse { duration_ms: 500, repeat: 1, interval_ms: 0 },
+            expect: vec![InputState { input: 1, state: 1 }],
+            timeout_ms: default_timeout_ms(),
+            retries: default_retries(),
+        };
+
+        config.commands.insert("arm".to_string(), command.clone());
+
+        assert!(config.validate(3).is_ok());
+
+        command.expect.clear();
+        config.commands.insert("arm".to_string(), command);
+
+        assert!(config.validate(3).is_err());
+    }
+}
@@ -0,0 +1,1075 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Duration as ChronoDuration, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::events::{self, EventKind};
+use crate::relay::{Pulse, PulseOutput, RegisterForStatus, RelayActor, RelayStatus, SetOutput, UnregisterForStatus};
+use crate::siren::{Chirp, ResetSirens, SirenActor, StartSiren};
+use crate::storage;
+use crate::users::{PinError, UserInfo, UsersActor, VerifyPin};
+use crate::zones::{Zone, ZoneStatus, ZoneType, zone_for, zone_statuses};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const ALARM_FILE: &str = "alarm.json";
+const ALARM_EVENTS_FILE: &str = "alarm_events.json";
+const MAX_ALARM_EVENTS: usize = 500;
+
+
+fn default_delay_secs() -> u64 {
+    30
+}
+
+fn default_cross_zone_window_secs() -> u64 {
+    60
+}
+
+fn default_swinger_limit() -> u32 {
+    3
+}
+
+fn default_max_pin_attempts() -> u32 {
+    5
+}
+
+fn default_pin_lockout_secs() -> u64 {
+    300
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DuressOutput {
+    pub output: usize,
+    #[serde(flatten)]
+    pub pulse: Pulse,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AlarmConfig {
+    #[serde(default = "default_delay_secs")]
+    pub exit_delay_secs: u64,
+    #[serde(default = "default_delay_secs")]
+    pub entry_delay_secs: u64,
+    #[serde(default)]
+    pub require_pin_to_arm: bool,
+    #[serde(default = "default_max_pin_attempts")]
+    pub max_pin_attempts: u32,
+    #[serde(default = "default_pin_lockout_secs")]
+    pub pin_lockout_secs: u64,
+    #[serde(default)]
+    pub duress_output: Option<DuressOutput>,
+    #[serde(default = "default_cross_zone_window_secs")]
+    pub cross_zone_window_secs: u64,
+    #[serde(default)]
+    pub pre_alarm_output: Option<usize>,
+    #[serde(default = "default_swinger_limit")]
+    pub swinger_limit: u32,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        AlarmConfig {
+            exit_delay_secs: default_delay_secs(),
+            entry_delay_secs: default_delay_secs(),
+            require_pin_to_arm: false,
+            max_pin_attempts: default_max_pin_attempts(),
+            pin_lockout_secs: default_pin_lockout_secs(),
+            duress_output: None,
+            cross_zone_window_secs: default_cross_zone_window_secs(),
+            pre_alarm_output: None,
+            swinger_limit: default_swinger_limit(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmState {
+    Disarmed,
+    Arming,
+    ArmedAway,
+    ArmedHome,
+    EntryDelay,
+    Alarm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArmMode {
+    Away,
+    Home,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmKind {
+    Intrusion,
+    Fire,
+    Tamper,
+    Panic,
+    Medical,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AlarmStatus {
+    pub state: AlarmState,
+    pub mode: Option<ArmMode>,
+    pub deadline: Option<NaiveDateTime>,
+    pub alarm_input: Option<usize>,
+    pub alarm_zone: Option<String>,
+    pub alarm_kind: Option<AlarmKind>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub bypassed: Vec<usize>,
+    #[serde(default)]
+    pub shutdown: Vec<usize>,
+    #[serde(default)]
+    pub silent: bool,
+    /// When the sirens were last started, so their cutoff still holds after a restart.
+    #[serde(default)]
+    pub alarm_started_at: Option<NaiveDateTime>,
+    pub changed_at: NaiveDateTime,
+}
+
+impl Default for AlarmStatus {
+    fn default() -> Self {
+        AlarmStatus {
+            state: AlarmState::Disarmed,
+            mode: None,
+            deadline: None,
+            alarm_input: None,
+            alarm_zone: None,
+            alarm_kind: None,
+            user: None,
+            bypassed: Vec::new(),
+            shutdown: Vec::new(),
+            silent: false,
+            alarm_started_at: None,
+            changed_at: Local::now().naive_local(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum AlarmError {
+    NotDisarmed { state: AlarmState },
+    NotArmed { state: AlarmState },
+    Unauthorized { reason: PinError },
+    NotReady { open_zones: Vec<ZoneStatus> },
+    InvalidBypass { inputs: Vec<usize> },
+    EventNotFound { id: u64 },
+    InvalidTransition { state: AlarmEventState },
+    WalkTestActive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmEventState {
+    Raised,
+    Acknowledged,
+    Resolved,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AlarmEvent {
+    pub id: u64,
+    pub state: AlarmEventState,
+    pub kind: AlarmKind,
+    pub input: Option<usize>,
+    pub zone: Option<String>,
+    pub raised_at: NaiveDateTime,
+    pub acknowledged_at: Option<NaiveDateTime>,
+    pub acknowledged_by: Option<String>,
+    pub comment: Option<String>,
+    pub resolved_at: Option<NaiveDateTime>,
+    pub resolved_by: Option<String>,
+    #[serde(default)]
+    pub silent: bool,
+    #[serde(default)]
+    pub client: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManualAlarmKind {
+    Panic,
+    Medical,
+    Fire,
+}
+
+#[derive(Deserialize)]
+pub struct RaiseAlarmRequest {
+    pub kind: ManualAlarmKind,
+    #[serde(default)]
+    pub silent: bool,
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AlarmEventsQuery {
+    pub state: Option<AlarmEventState>,
+}
+
+#[derive(Deserialize)]
+pub struct AcknowledgeRequest {
+    #[serde(default)]
+    pub pin: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Readiness {
+    pub mode: ArmMode,
+    pub ready: bool,
+    pub inputs_known: bool,
+    pub open_zones: Vec<ZoneStatus>,
+}
+
+#[derive(Deserialize)]
+pub struct ReadinessQuery {
+    pub mode: ArmMode,
+}
+
+#[derive(Deserialize)]
+pub struct ArmRequest {
+    pub mode: ArmMode,
+    #[serde(default)]
+    pub pin: Option<String>,
+    #[serde(default)]
+    pub bypass: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct PinRequest {
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "AlarmStatus")]
+pub struct GetAlarmStatus;
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmStatus, AlarmError>")]
+pub struct Arm {
+    pub mode: ArmMode,
+    pub pin: Option<String>,
+    pub bypass: Vec<usize>,
+    pub client: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<AlarmEvent>")]
+pub struct GetAlarmEvents {
+    pub state: Option<AlarmEventState>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmEvent, AlarmError>")]
+pub struct AcknowledgeAlarmEvent {
+    pub id: u64,
+    pub pin: Option<String>,
+    pub comment: Option<String>,
+    pub client: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmEvent, AlarmError>")]
+pub struct ResolveAlarmEvent {
+    pub id: u64,
+    pub pin: Option<String>,
+    pub client: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmStatus, AlarmError>")]
+pub struct SystemArm {
+    pub mode: ArmMode,
+    pub source: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmStatus, AlarmError>")]
+pub struct SystemDisarm {
+    pub source: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmEvent, AlarmError>")]
+pub struct RaiseAlarm {
+    pub kind: ManualAlarmKind,
+    pub silent: bool,
+    pub client: String,
+    pub pin: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), AlarmError>")]
+pub struct SetWalkTest {
+    pub active: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "Readiness")]
+pub struct GetReadiness {
+    pub mode: ArmMode,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AlarmStatus, AlarmError>")]
+pub struct Disarm {
+    pub pin: Option<String>,
+    pub client: String,
+}
+
+pub struct AlarmActor {
+    id: usize,
+    data_dir: String,
+    config: AlarmConfig,
+    zones: Vec<Zone>,
+    status: AlarmStatus,
+    alarm_events: Vec<AlarmEvent>,
+    pending_verification: Vec<(Zone, Instant)>,
+    alarm_counts: HashMap<usize, u32>,
+    walk_test: bool,
+    inputs: Vec<u32>,
+    inputs_known: bool,
+}
+
+impl Default for AlarmActor {
+    fn default() -> Self {
+        AlarmActor {
+            id: 0,
+            data_dir: "data".to_string(),
+            config: AlarmConfig::default(),
+            zones: Vec::new(),
+            status: AlarmStatus::default(),
+            alarm_events: Vec::new(),
+            pending_verification: Vec::new(),
+            alarm_counts: HashMap::new(),
+            walk_test: false,
+            inputs: Vec::new(),
+            inputs_known: false,
+        }
+    }
+}
+
+impl Actor for AlarmActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.status = storage::load(&self.status_path());
+        self.alarm_events = storage::load(&self.alarm_events_path());
+
+        // The sirens do not survive a restart, so an alarm that was still active sounds again
+        // for whatever is left of its cutoff.
+        if self.status.state == AlarmState::Alarm && !self.status.silent {
+            SirenActor::from_registry().do_send(StartSiren {
+                kind: self.status.alarm_kind.unwrap_or(AlarmKind::Intrusion),
+                started_at: self.status.alarm_started_at.unwrap_or(self.status.changed_at),
+            });
+        }
+
+        RelayActor::from_registry().send(RegisterForStatus(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        ctx.run_interval(TICK_INTERVAL, |act, _| act.tick());
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+
+        Running::Stop
+    }
+}
+
+impl Supervised for AlarmActor {}
+
+impl SystemService for AlarmActor {}
+
+impl AlarmKind {
+    pub fn for_zone(zone_type: ZoneType) -> AlarmKind {
+        match zone_type {
+            ZoneType::Fire => AlarmKind::Fire,
+            ZoneType::Tamper => AlarmKind::Tamper,
+            ZoneType::Panic => AlarmKind::Panic,
+            _ => AlarmKind::Intrusion,
+        }
+    }
+}
+
+/// Identifies who raised a manual alarm; a wrong PIN here is not counted towards a lockout.
+async fn identify(pin: Option<String>, client: String) -> Option<String> {
+    match UsersActor::from_registry().send(VerifyPin { pin, required: false, admin: false, installer: false, count_failures: false, client }).await {
+        Ok(Ok(user)) => user.map(|user| user.name),
+        _ => None,
+    }
+}
+
+async fn verify_pin(pin: Option<String>, required: bool, client: String) -> Result<Option<UserInfo>, AlarmError> {
+    match UsersActor::from_registry().send(VerifyPin { pin, required, admin: false, installer: false, count_failures: true, client }).await {
+        Ok(Ok(user)) => Ok(user),
+        Ok(Err(reason)) => Err(AlarmError::Unauthorized { reason }),
+        Err(_) => Err(AlarmError::Unauthorized { reason: PinError::PinRequired }),
+    }
+}
+
+impl From<ManualAlarmKind> for AlarmKind {
+    fn from(kind: ManualAlarmKind) -> Self {
+        match kind {
+            ManualAlarmKind::Panic => AlarmKind::Panic,
+            ManualAlarmKind::Medical => AlarmKind::Medical,
+            ManualAlarmKind::Fire => AlarmKind::Fire,
+        }
+    }
+}
+
+impl AlarmActor {
+    pub fn new(data_dir: &str, config: AlarmConfig, zones: Vec<Zone>) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            config,
+            zones,
+            ..AlarmActor::default()
+        }
+    }
+
+    fn status_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, ALARM_FILE)
+    }
+
+    fn alarm_events_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, ALARM_EVENTS_FILE)
+    }
+
+    /// Enters the alarm state and records the alarm event, `client` and `user` being set for a manual alarm.
+    /// Fire takes precedence: while a fire alarm sounds, other alarms only record their own event.
+    fn enter_alarm(&mut self, kind: AlarmKind, input: Option<usize>, zone: Option<String>, silent: bool,
+                   client: Option<String>, user: Option<String>) -> AlarmEvent {
+        let fire_sounding = self.status.state == AlarmState::Alarm && self.status.alarm_kind == Some(AlarmKind::Fire)
+            && !self.status.silent;
+
+        if !fire_sounding || kind == AlarmKind::Fire {
+            self.status.silent = silent;
+            self.status.alarm_input = input;
+            self.status.alarm_zone = zone.clone();
+            self.status.alarm_kind = Some(kind);
+            self.set_state(AlarmState::Alarm, None);
+        }
+
+        self.raise_alarm_event(kind, input, zone, client, user)
+    }
+
+    fn raise_alarm_event(&mut self, kind: AlarmKind, input: Option<usize>, zone: Option<String>,
+                         client: Option<String>, user: Option<String>) -> AlarmEvent {
+        let event = AlarmEvent {
+            id: self.alarm_events.iter().map(|event| event.id).max().unwrap_or(0) + 1,
+            state: AlarmEventState::Raised,
+            kind,
+            input,
+            zone,
+            raised_at: Local::now().naive_local(),
+            acknowledged_at: None,
+            acknowledged_by: None,
+            comment: None,
+            resolved_at: None,
+            resolved_by: None,
+            silent: self.status.silent,
+            client,
+            user,
+        };
+
+        // Handled events go first; raised events are never dropped, so the list may outgrow the limit until they are handled.
+        if self.alarm_events.len() >= MAX_ALARM_EVENTS {
+            let index = self.alarm_events.iter().position(|event| event.state == AlarmEventState::Resolved)
+                .or_else(|| self.alarm_events.iter().position(|event| event.state == AlarmEventState::Acknowledged));
+
+            if let Some(index) = index {
+                self.alarm_events.remove(index);
+            }
+        }
+
+        println!("Alarm event {} raised", event.id);
+
+        self.alarm_events.push(event.clone());
+        self.alarm_event_changed(event.clone());
+
+        event
+    }
+
+    fn alarm_event_changed(&self, event: AlarmEvent) {
+        storage::save(&self.alarm_events_path(), &self.alarm_events);
+        events::publish(EventKind::AlarmEventChanged { alarm: event });
+    }
+
+    fn update_alarm_event(&mut self, id: u64, from: AlarmEventState, to: AlarmEventState, user: Option<String>,
+                          comment: Option<String>) -> Result<AlarmEvent, AlarmError> {
+        let event = self.alarm_events.iter_mut()
+            .find(|event| event.id == id)
+            .ok_or(AlarmError::EventNotFound { id })?;
+
+        if event.state != from {
+            return Err(AlarmError::InvalidTransition { state: event.state });
+        }
+
+        let now = Local::now().naive_local();
+
+        event.state = to;
+
+        if to == AlarmEventState::Acknowledged {
+            event.acknowledged_at = Some(now);
+            event.acknowledged_by = user;
+            event.comment = comment;
+        } else {
+            event.resolved_at = Some(now);
+            event.resolved_by = user;
+        }
+
+        println!("Alarm event {} {:?}", id, to);
+
+        let event = event.clone();
+
+        self.alarm_event_changed(event.clone());
+
+        Ok(event)
+    }
+
+    fn set_state(&mut self, state: AlarmState, delay_secs: Option<u64>) {
+        let now = Local::now().naive_local();
+
+        let previous = self.status.state;
+
+        println!("Alarm state {:?} -> {:?}", previous, state);
+
+        self.status.state = state;
+        self.status.changed_at = now;
+        self.status.deadline = delay_secs.map(|secs| now + ChronoDuration::seconds(secs as i64));
+
+        if state == AlarmState::Disarmed {
+            self.status.mode = None;
+            self.status.alarm_input = None;
+            self.status.alarm_zone = None;
+            self.status.bypassed.clear();
+            self.status.shutdown.clear();
+            self.status.silent = false;
+            self.status.alarm_started_at = None;
+            self.alarm_counts.clear();
+            self.status.alarm_kind = None;
+        }
+
+        if state == AlarmState::Alarm {
+            self.status.alarm_started_at = Some(now);
+        }
+
+        if state == AlarmState::Disarmed || state == AlarmState::Alarm {
+            self.clear_pending_verification();
+        }
+
+        match state {
+            AlarmState::Alarm => if !self.status.silent {
+                SirenActor::from_registry().do_send(StartSiren {
+                    kind: self.status.alarm_kind.unwrap_or(AlarmKind::Intrusion),
+                    started_at: now,
+                });
+            },
+            AlarmState::Arming | AlarmState::ArmedAway | AlarmState::ArmedHome if previous == AlarmState::Disarmed =>
+                SirenActor::from_registry().do_send(Chirp { count: 1 }),
+            AlarmState::Disarmed => {
+                SirenActor::from_registry().do_send(ResetSirens);
+                SirenActor::from_registry().do_send(Chirp { count: 2 });
+            }
+            _ => (),
+        }
+
+        storage::save(&self.status_path(), &self.status);
+        events::publish(EventKind::AlarmStateChanged { status: self.status.clone() });
+    }
+
+    fn readiness(&self, mode: ArmMode, bypass: &[usize]) -> Readiness {
+        let open_zones = zone_statuses(&self.zones, &self.inputs)
+            .into_iter()
+            .filter(|zone| zone.open && !bypass.contains(&zone.input))
+            .filter(|zone| !zone.zone_type.is_always_armed() && zone.zone_type != ZoneType::ChimeOnly)
+            .filter(|zone| mode == ArmMode::Away || !zone_for(&self.zones, zone.input).map_or(false, |zone| zone.interior))
+            .collect::<Vec<ZoneStatus>>();
+
+        // Until the relay reports its inputs no zone can be confirmed closed.
+        let inputs_known = self.inputs_known;
+
+        Readiness {
+            mode,
+            ready: inputs_known && open_zones.is_empty(),
+            inputs_known,
+            open_zones,
+        }
+    }
+
+    /// Inputs that do not exist, are not monitored or whose zones must never be bypassed, such as fire or panic.
+    fn invalid_bypass(&self, bypass: &[usize]) -> Vec<usize> {
+        bypass.iter()
+            .copied()
+            .filter(|&input| input == 0 || input > self.inputs.len()
+                || zone_for(&self.zones, input).map_or(true, |zone| zone.zone_type.is_always_armed()))
+            .collect()
+    }
+
+    fn armed_state(mode: ArmMode) -> AlarmState {
+        match mode {
+            ArmMode::Away => AlarmState::ArmedAway,
+            ArmMode::Home => AlarmState::ArmedHome,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.expire_pending_verification();
+
+        let expired = self.status.deadline.map_or(false, |deadline| Local::now().naive_local() >= deadline);
+
+        if !expired {
+            return;
+        }
+
+        match (self.status.state, self.status.mode) {
+            (AlarmState::Arming, Some(mode)) => self.set_state(AlarmActor::armed_state(mode), None),
+            (AlarmState::EntryDelay, _) => {
+                let kind = self.status.alarm_kind.unwrap_or(AlarmKind::Intrusion);
+
+                self.enter_alarm(kind, self.status.alarm_input, self.status.alarm_zone.clone(), false, None, None);
+            }
+            _ => self.status.deadline = None,
+        }
+    }
+
+    fn trigger_alarm(&mut self, zone: &Zone) {
+        // Only a manual alarm can be silent; a zone tripping sounds the sirens even during one.
+        self.enter_alarm(AlarmKind::for_zone(zone.zone_type), Some(zone.input), Some(zone.name.clone()), false, None, None);
+        self.count_alarm(zone);
+    }
+
+    fn arm(&mut self, mode: ArmMode, bypass: Vec<usize>, user: Option<String>) -> Result<AlarmStatus, AlarmError> {
+        if self.status.state != AlarmState::Disarmed {
+            return Err(AlarmError::NotDisarmed { state: self.status.state });
+        }
+
+        if self.walk_test {
+            return Err(AlarmError::WalkTestActive);
+        }
+
+        let invalid = self.invalid_bypass(&bypass);
+
+        if !invalid.is_empty() {
+            return Err(AlarmError::InvalidBypass { inputs: invalid });
+        }
+
+        let readiness = self.readiness(mode, &bypass);
+
+        if !readiness.ready {
+            return Err(AlarmError::NotReady { open_zones: readiness.open_zones });
+        }
+
+        println!("Alarm armed {:?} by {}", mode, user.as_deref().unwrap_or("anonymous"));
+
+        self.status.mode = Some(mode);
+        self.status.bypassed = bypass;
+        self.status.user = user;
+
+        if self.config.exit_delay_secs == 0 {
+            self.set_state(AlarmActor::armed_state(mode), None);
+        } else {
+            self.set_state(AlarmState::Arming, Some(self.config.exit_delay_secs));
+        }
+
+        Ok(self.status.clone())
+    }
+
+    fn disarm(&mut self, user: Option<String>) -> AlarmStatus {
+        println!("Alarm disarmed by {}", user.as_deref().unwrap_or("anonymous"));
+
+        self.status.user = user;
+        self.set_state(AlarmState::Disarmed, None);
+
+        self.status.clone()
+    }
+
+    fn raise_manual_alarm(&mut self, kind: ManualAlarmKind, silent: bool, client: String, user: Option<String>) -> AlarmEvent {
+        println!("{:?} alarm raised by {} ({})", kind, user.as_deref().unwrap_or("anonymous"), client);
+
+        // A silent alarm stays silent until disarmed, but never quietens one that is already sounding.
+        let silent = silent && (self.status.state != AlarmState::Alarm || self.status.silent);
+
+        self.enter_alarm(kind.into(), None, None, silent, Some(client), user)
+    }
+
+    fn count_alarm(&mut self, zone: &Zone) {
+        // Trips only count towards a shutdown within an arming period.
+        if self.status.mode.is_none() {
+            return;
+        }
+
+        let count = self.alarm_counts.entry(zone.input).or_insert(0);
+
+        *count += 1;
+
+        let alarms = *count;
+
+        if self.config.swinger_limit == 0 || alarms < self.config.swinger_limit || zone.zone_type.is_always_armed() {
+            return;
+        }
+
+        if self.status.shutdown.contains(&zone.input) {
+            return;
+        }
+
+        println!("Zone {} shut down after {} alarms", zone.name, alarms);
+
+        self.status.shutdown.push(zone.input);
+
+        if !self.status.bypassed.contains(&zone.input) {
+            self.status.bypassed.push(zone.input);
+        }
+
+        storage::save(&self.status_path(), &self.status);
+        events::publish(EventKind::ZoneShutdown { zone: zone.name.clone(), input: zone.input, alarms });
+    }
+
+    fn verify_cross_zone(&mut self, zone: Zone) {
+        // A link may be listed on either zone, so both directions are checked.
+        if !self.zones.iter().any(|other| other.is_linked_to(&zone)) {
+            self.trigger_alarm(&zone);
+
+            return;
+        }
+
+        self.expire_pending_verification();
+
+        if self.pending_verification.iter().any(|(pending, _)| pending.is_linked_to(&zone)) {
+            println!("Cross-zone verification confirmed by {}", zone.name);
+
+            self.trigger_alarm(&zone);
+
+            return;
+        }
+
+        if self.pending_verification.iter().any(|(pending, _)| pending.input == zone.input) {
+            return;
+        }
+
+        println!("Zone {} pending verification", zone.name);
+
+        if self.pending_verification.is_empty() {
+            if let Some(output) = self.config.pre_alarm_output {
+                RelayActor::from_registry().do_send(SetOutput { number: output, state: 1 });
+            }
+        }
+
+        events::publish(EventKind::PendingVerification {
+            zone: zone.name.clone(),
+            input: zone.input,
+            window_secs: self.config.cross_zone_window_secs,
+        });
+
+        self.pending_verification.push((zone, Instant::now()));
+    }
+
+    fn expire_pending_verification(&mut self) {
+        let window = Duration::from_secs(self.config.cross_zone_window_secs);
+        let (expired, pending) = std::mem::take(&mut self.pending_verification)
+            .into_iter()
+            .partition::<Vec<(Zone, Instant)>, _>(|(_, since)| since.elapsed() >= window);
+
+        self.pending_verification = pending;
+
+        if expired.is_empty() {
+            return;
+        }
+
+        for (zone, _) in expired {
+            println!("Zone {} was not verified", zone.name);
+
+            events::publish(EventKind::VerificationExpired { zone: zone.name, input: zone.input });
+        }
+
+        if self.pending_verification.is_empty() {
+            self.reset_pre_alarm_output();
+        }
+    }
+
+    fn clear_pending_verification(&mut self) {
+        if !self.pending_verification.is_empty() {
+            self.pending_verification.clear();
+            self.reset_pre_alarm_output();
+        }
+    }
+
+    fn reset_pre_alarm_output(&self) {
+        if let Some(output) = self.config.pre_alarm_output {
+            RelayActor::from_registry().do_send(SetOutput { number: output, state: 0 });
+        }
+    }
+
+    fn raise_duress(&self, user: &str) {
+        events::publish(EventKind::Duress { user: user.to_string() });
+
+        if let Some(ref duress) = self.config.duress_output {
+            RelayActor::from_registry().do_send(PulseOutput { number: duress.output, pulse: duress.pulse.clone() });
+        }
+    }
+
+    fn handle_input(&mut self, zone: Zone) {
+        let input = zone.input;
+        let state = self.status.state;
+
+        // Life-safety and tamper zones stay live while the installer walks the premises.
+        if self.walk_test && !zone.zone_type.is_always_armed() {
+            return;
+        }
+
+        if self.status.bypassed.contains(&input) && !zone.zone_type.is_always_armed() {
+            return;
+        }
+
+        if zone.zone_type == ZoneType::ChimeOnly {
+            events::publish(EventKind::Chime { zone: zone.name });
+
+            return;
+        }
+
+        // The zone that raised the running alarm is only counted when it trips again,
+        // any other zone raises its own alarm.
+        let retrigger = state == AlarmState::Alarm && self.status.alarm_input == Some(input);
+
+        if zone.zone_type.is_always_armed() {
+            if retrigger {
+                self.count_alarm(&zone);
+            } else {
+                self.trigger_alarm(&zone);
+            }
+
+            return;
+        }
+
+        if self.status.mode == Some(ArmMode::Home) && zone.interior {
+            return;
+        }
+
+        match (state, zone.zone_type) {
+            (AlarmState::ArmedAway, ZoneType::EntryExit) | (AlarmState::ArmedHome, ZoneType::EntryExit) => {
+                self.status.alarm_input = Some(zone.input);
+                self.status.alarm_zone = Some(zone.name);
+                self.set_state(AlarmState::EntryDelay, Some(self.config.entry_delay_secs));
+            }
+            (AlarmState::EntryDelay, ZoneType::EntryExit) => (),
+            (AlarmState::EntryDelay, _) if zone.interior => (),
+            (AlarmState::ArmedAway, _) | (AlarmState::ArmedHome, _) | (AlarmState::EntryDelay, _) => self.verify_cross_zone(zone),
+            (AlarmState::Alarm, _) if retrigger => self.count_alarm(&zone),
+            (AlarmState::Alarm, _) => self.trigger_alarm(&zone),
+            _ => (),
+        }
+    }
+}
+
+impl Handler<RelayStatus> for AlarmActor {
+    type Result = ();
+
+    fn handle(&mut self, message: RelayStatus, _: &mut Self::Context) -> Self::Result {
+        if !message.connected {
+            self.inputs_known = false;
+        }
+
+        if let Some(inputs) = message.inputs {
+            self.inputs_known = message.connected;
+
+            let previous = std::mem::replace(&mut self.inputs, inputs);
+
+            if previous.is_empty() {
+                return;
+            }
+
+            let inputs = &self.inputs;
+            let activated = self.zones.iter()
+                .filter(|zone| {
+                    let index = zone.input.wrapping_sub(1);
+
+                    match (inputs.get(index), previous.get(index)) {
+                        (Some(&state), Some(&before)) => zone.is_open(state) && !zone.is_open(before),
+                        _ => false,
+                    }
+                })
+                .cloned()
+                .collect::<Vec<Zone>>();
+
+            for zone in activated {
+                self.handle_input(zone);
+            }
+        }
+    }
+}
+
+impl Handler<GetAlarmStatus> for AlarmActor {
+    type Result = MessageResult<GetAlarmStatus>;
+
+    fn handle(&mut self, _: GetAlarmStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.status.clone())
+    }
+}
+
+impl Handler<Arm> for AlarmActor {
+    type Result = ResponseActFuture<Self, Result<AlarmStatus, AlarmError>>;
+
+    fn handle(&mut self, message: Arm, _: &mut Context<Self>) -> Self::Result {
+        let mode = message.mode;
+        let mut bypass = message.bypass;
+
+        bypass.sort_unstable();
+        bypass.dedup();
+
+        Box::pin(verify_pin(message.pin, self.config.require_pin_to_arm, message.client)
+            .into_actor(self)
+            .map(move |res, act, _| {
+                let user = res?;
+
+                act.arm(mode, bypass, user.map(|user| user.name))
+            }))
+    }
+}
+
+impl Handler<SystemArm> for AlarmActor {
+    type Result = Result<AlarmStatus, AlarmError>;
+
+    fn handle(&mut self, message: SystemArm, _: &mut Context<Self>) -> Self::Result {
+        self.arm(message.mode, Vec::new(), Some(message.source))
+    }
+}
+
+impl Handler<SystemDisarm> for AlarmActor {
+    type Result = Result<AlarmStatus, AlarmError>;
+
+    fn handle(&mut self, message: SystemDisarm, _: &mut Context<Self>) -> Self::Result {
+        match self.status.state {
+            AlarmState::ArmedAway | AlarmState::ArmedHome | AlarmState::Arming | AlarmState::EntryDelay =>
+                Ok(self.disarm(Some(message.source))),
+            state => Err(AlarmError::NotArmed { state }),
+        }
+    }
+}
+
+impl Handler<Disarm> for AlarmActor {
+    type Result = ResponseActFuture<Self, Result<AlarmStatus, AlarmError>>;
+
+    fn handle(&mut self, message: Disarm, _: &mut Context<Self>) -> Self::Result {
+        Box::pin(verify_pin(message.pin, true, message.client)
+            .into_actor(self)
+            .map(|res, act, _| {
+                let user = res?;
+
+                // A duress code disarms like any other PIN so the panel gives nothing away;
+                // the public status does not name who disarmed in that case.
+                let user = match user {
+                    Some(user) if user.duress => {
+                        act.raise_duress(&user.name);
+                        None
+                    }
+                    user => user.map(|user| user.name),
+                };
+
+                Ok(act.disarm(user))
+            }))
+    }
+}
+
+impl Handler<GetReadiness> for AlarmActor {
+    type Result = MessageResult<GetReadiness>;
+
+    fn handle(&mut self, message: GetReadiness, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.readiness(message.mode, &[]))
+    }
+}
+
+impl Handler<GetAlarmEvents> for AlarmActor {
+    type Result = MessageResult<GetAlarmEvents>;
+
+    fn handle(&mut self, message: GetAlarmEvents, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.alarm_events.iter()
+            .filter(|event| message.state.map_or(true, |state| event.state == state))
+            .cloned()
+            .collect())
+    }
+}
+
+impl Handler<AcknowledgeAlarmEvent> for AlarmActor {
+    type Result = ResponseActFuture<Self, Result<AlarmEvent, AlarmError>>;
+
+    fn handle(&mut self, message: AcknowledgeAlarmEvent, _: &mut Context<Self>) -> Self::Result {
+        let AcknowledgeAlarmEvent { id, pin, comment, client } = message;
+
+        Box::pin(verify_pin(pin, true, client)
+            .into_actor(self)
+            .map(move |res, act, _| {
+                let user = res?.map(|user| user.name);
+
+                act.update_alarm_event(id, AlarmEventState::Raised, AlarmEventState::Acknowledged, user, comment)
+            }))
+    }
+}
+
+impl Handler<ResolveAlarmEvent> for AlarmActor {
+    type Result = ResponseActFuture<Self, Result<AlarmEvent, AlarmError>>;
+
+    fn handle(&mut self, message: ResolveAlarmEvent, _: &mut Context<Self>) -> Self::Result {
+        let id = message.id;
+
+        Box::pin(verify_pin(message.pin, true, message.client)
+            .into_actor(self)
+            .map(move |res, act, _| {
+                let user = res?.map(|user| user.name);
+
+                act.update_alarm_event(id, AlarmEventState::Acknowledged, AlarmEventState::Resolved, user, None)
+            }))
+    }
+}
+
+impl Handler<SetWalkTest> for AlarmActor {
+    type Result = Result<(), AlarmError>;
+
+    fn handle(&mut self, message: SetWalkTest, _: &mut Context<Self>) -> Self::Result {
+        if message.active && self.status.state != AlarmState::Disarmed {
+            return Err(AlarmError::NotDisarmed { state: self.status.state });
+        }
+
+        self.walk_test = message.active;
+
+        Ok(())
+    }
+}
+
+impl Handler<RaiseAlarm> for AlarmActor {
+    type Result = ResponseActFuture<Self, Result<AlarmEvent, AlarmError>>;
+
+    fn handle(&mut self, message: RaiseAlarm, _: &mut Context<Self>) -> Self::Result {
+        let RaiseAlarm { kind, silent, client, pin } = message;
+
+        // An emergency is never refused; a wrong PIN only means the user stays unknown.
+        Box::pin(identify(pin, client.clone())
+            .into_actor(self)
+            .map(move |user, act, _| Ok(act.raise_manual_alarm(kind, silent, client, user))))
+    }
+}
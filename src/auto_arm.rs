@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::alarm::{AlarmActor, AlarmError, ArmMode, SystemArm, SystemDisarm};
+use crate::calendar::{self, Calendars};
+use crate::events::{self, EventKind};
+use crate::relay::{Pulse, PulseOutput, RelayActor};
+use crate::scheduler::{GetCalendars, SchedulerActor};
+use crate::storage;
+use crate::zones::ZoneStatus;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const AUTO_ARM_FILE: &str = "auto_arm.json";
+
+
+fn default_warning_secs() -> u64 {
+    300
+}
+
+fn default_postpone_secs() -> u64 {
+    1800
+}
+
+fn default_max_postpone_secs() -> u64 {
+    7200
+}
+
+fn default_max_postpones() -> u32 {
+    3
+}
+
+fn default_warning_pulse() -> Pulse {
+    Pulse {
+        duration_ms: 500,
+        repeat: 3,
+        interval_ms: 500,
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AutoArmConfig {
+    #[serde(default = "default_warning_secs")]
+    pub warning_secs: u64,
+    #[serde(default)]
+    pub warning_output: Option<usize>,
+    #[serde(default = "default_warning_pulse")]
+    pub warning_pulse: Pulse,
+    #[serde(default = "default_postpone_secs")]
+    pub postpone_secs: u64,
+    #[serde(default = "default_max_postpone_secs")]
+    pub max_postpone_secs: u64,
+    /// How often one pending arm may be postponed before it goes ahead regardless.
+    #[serde(default = "default_max_postpones")]
+    pub max_postpones: u32,
+}
+
+impl Default for AutoArmConfig {
+    fn default() -> Self {
+        AutoArmConfig {
+            warning_secs: default_warning_secs(),
+            warning_output: None,
+            warning_pulse: default_warning_pulse(),
+            postpone_secs: default_postpone_secs(),
+            max_postpone_secs: default_max_postpone_secs(),
+            max_postpones: default_max_postpones(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AutoArmAction {
+    Arm { mode: ArmMode },
+    Disarm,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AutoArmSchedule {
+    #[serde(flatten)]
+    pub action: AutoArmAction,
+    pub time: NaiveTime,
+    #[serde(default)]
+    pub days: Vec<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+pub type AutoArmSchedules = HashMap<String, AutoArmSchedule>;
+
+#[derive(Clone, Serialize)]
+pub struct PendingArm {
+    pub schedule: String,
+    pub mode: ArmMode,
+    pub arm_at: NaiveDateTime,
+    pub warned: bool,
+    pub postponed: u32,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AutoArmFailure {
+    pub schedule: String,
+    pub time: NaiveDateTime,
+    pub error: AlarmError,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AutoArmStatus {
+    pub pending: Option<PendingArm>,
+    pub last_failure: Option<AutoArmFailure>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct PostponeRequest {
+    #[serde(default)]
+    pub secs: Option<u64>,
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum AutoArmError {
+    NothingPending,
+    PostponeLimit { max_postpones: u32 },
+    InvalidPostpone,
+}
+
+#[derive(Message)]
+#[rtype(result = "AutoArmSchedules")]
+pub struct GetAutoArmSchedules;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetAutoArmSchedule {
+    pub name: String,
+    pub schedule: AutoArmSchedule,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct DeleteAutoArmSchedule {
+    pub name: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "AutoArmStatus")]
+pub struct GetAutoArmStatus;
+
+#[derive(Message)]
+#[rtype(result = "Result<AutoArmStatus, AutoArmError>")]
+pub struct PostponeAutoArm {
+    pub secs: Option<u64>,
+}
+
+pub struct AutoArmActor {
+    data_dir: String,
+    config: AutoArmConfig,
+    schedules: AutoArmSchedules,
+    pending: Option<PendingArm>,
+    last_failure: Option<AutoArmFailure>,
+    last_tick: Option<NaiveDateTime>,
+}
+
+impl Default for AutoArmActor {
+    fn default() -> Self {
+        AutoArmActor {
+            data_dir: "data".to_string(),
+            config: AutoArmConfig::default(),
+            schedules: HashMap::new(),
+            pending: None,
+            last_failure: None,
+            last_tick: None,
+        }
+    }
+}
+
+impl Actor for AutoArmActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.schedules = storage::load(&self.schedules_path());
+
+        ctx.run_interval(TICK_INTERVAL, |act, ctx| {
+            SchedulerActor::from_registry().send(GetCalendars)
+                .into_actor(act)
+                .then(|res, act, ctx| {
+                    if let Ok(calendars) = res {
+                        act.tick(&calendars, ctx);
+                    }
+
+                    fut::ready(())
+                })
+                .spawn(ctx);
+        });
+    }
+}
+
+impl Supervised for AutoArmActor {}
+
+impl SystemService for AutoArmActor {}
+
+impl AutoArmSchedule {
+    fn due_at(&self, calendars: &Calendars, from: NaiveDateTime, to: NaiveDateTime, lead_secs: u64) -> Option<NaiveDateTime> {
+        let lead = ChronoDuration::seconds(lead_secs as i64);
+
+        [from.date(), to.date(), (to + lead).date()].iter()
+            .map(|date| date.and_time(self.time))
+            .find(|&at| {
+                let date = at.date();
+
+                from < at - lead && at - lead <= to
+                    && (self.days.is_empty() || self.days.contains(&date.weekday().number_from_monday()))
+                    && calendar::matches(calendars, &self.include, &self.exclude, date)
+            })
+    }
+}
+
+impl AutoArmActor {
+    pub fn new(data_dir: &str, config: AutoArmConfig) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            config,
+            ..AutoArmActor::default()
+        }
+    }
+
+    fn schedules_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, AUTO_ARM_FILE)
+    }
+
+    fn status(&self) -> AutoArmStatus {
+        AutoArmStatus {
+            pending: self.pending.clone(),
+            last_failure: self.last_failure.clone(),
+        }
+    }
+
+    fn tick(&mut self, calendars: &Calendars, ctx: &mut <Self as Actor>::Context) {
+        let now = Local::now().naive_local();
+        let mut disarms = Vec::new();
+
+        if let Some(last_tick) = self.last_tick {
+            for (name, schedule) in &self.schedules {
+                match schedule.action {
+                    AutoArmAction::Arm { mode } => {
+                        let lead_secs = self.config.warning_secs;
+
+                        if let Some(arm_at) = schedule.due_at(calendars, last_tick, now, lead_secs) {
+                            println!("Auto-arm {} scheduled at {}", name, arm_at);
+
+                            self.pending = Some(PendingArm {
+                                schedule: name.clone(),
+                                mode,
+                                arm_at,
+                                warned: false,
+                                postponed: 0,
+                            });
+                        }
+                    }
+                    AutoArmAction::Disarm => {
+                        if schedule.due_at(calendars, last_tick, now, 0).is_some() {
+                            println!("Auto-disarm {}", name);
+
+                            disarms.push(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        self.last_tick = Some(now);
+
+        for name in disarms {
+            self.pending = None;
+            self.disarm(name, ctx);
+        }
+
+        let warning = ChronoDuration::seconds(self.config.warning_secs as i64);
+
+        if let Some(ref mut pending) = self.pending {
+            if !pending.warned && now >= pending.arm_at - warning {
+                pending.warned = true;
+
+                if let Some(output) = self.config.warning_output {
+                    RelayActor::from_registry().do_send(PulseOutput { number: output, pulse: self.config.warning_pulse.clone() });
+                }
+
+                events::publish(EventKind::AutoArmWarning { schedule: pending.schedule.clone(), arm_at: pending.arm_at });
+            }
+        }
+
+        let due = self.pending.as_ref().map_or(false, |pending| now >= pending.arm_at);
+
+        if due {
+            if let Some(pending) = self.pending.take() {
+                self.arm(pending, ctx);
+            }
+        }
+    }
+
+    fn arm(&mut self, pending: PendingArm, ctx: &mut <Self as Actor>::Context) {
+        AlarmActor::from_registry().send(SystemArm { mode: pending.mode, source: format!("schedule:{}", pending.schedule) })
+            .into_actor(self)
+            .then(move |res, act, _| {
+                match res {
+                    Ok(Ok(_)) => println!("Auto-arm {} succeeded", pending.schedule),
+                    Ok(Err(AlarmError::NotDisarmed { .. })) => println!("Auto-arm {} skipped, already armed", pending.schedule),
+                    Ok(Err(error)) => {
+                        println!("Auto-arm {} failed: {:?}", pending.schedule, error);
+
+                        let open_zones = match error {
+                            AlarmError::NotReady { ref open_zones } => open_zones.clone(),
+                            _ => Vec::<ZoneStatus>::new(),
+                        };
+
+                        events::publish(EventKind::AutoArmFailed { schedule: pending.schedule.clone(), open_zones });
+
+                        act.last_failure = Some(AutoArmFailure {
+                            schedule: pending.schedule,
+                            time: Local::now().naive_local(),
+                            error,
+                        });
+                    }
+                    Err(_) => (),
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
+    fn disarm(&mut self, schedule: String, ctx: &mut <Self as Actor>::Context) {
+        AlarmActor::from_registry().send(SystemDisarm { source: format!("schedule:{}", schedule) })
+            .into_actor(self)
+            .then(move |res, _, _| {
+                if let Ok(Err(error)) = res {
+                    println!("Auto-disarm {} skipped: {:?}", schedule, error);
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Handler<GetAutoArmSchedules> for AutoArmActor {
+    type Result = MessageResult<GetAutoArmSchedules>;
+
+    fn handle(&mut self, _: GetAutoArmSchedules, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.schedules.clone())
+    }
+}
+
+impl Handler<SetAutoArmSchedule> for AutoArmActor {
+    type Result = ();
+
+    fn handle(&mut self, message: SetAutoArmSchedule, _: &mut Context<Self>) -> Self::Result {
+        self.schedules.insert(message.name, message.schedule);
+        storage::save(&self.schedules_path(), &self.schedules);
+    }
+}
+
+impl Handler<DeleteAutoArmSchedule> for AutoArmActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: DeleteAutoArmSchedule, _: &mut Context<Self>) -> Self::Result {
+        self.schedules.remove(&message.name).ok_or(())?;
+        storage::save(&self.schedules_path(), &self.schedules);
+
+        if self.pending.as_ref().map_or(false, |pending| pending.schedule == message.name) {
+            self.pending = None;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<GetAutoArmStatus> for AutoArmActor {
+    type Result = MessageResult<GetAutoArmStatus>;
+
+    fn handle(&mut self, _: GetAutoArmStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<PostponeAutoArm> for AutoArmActor {
+    type Result = Result<AutoArmStatus, AutoArmError>;
+
+    fn handle(&mut self, message: PostponeAutoArm, _: &mut Context<Self>) -> Self::Result {
+        let secs = message.secs.unwrap_or(self.config.postpone_secs).min(self.config.max_postpone_secs);
+        let pending = self.pending.as_mut().ok_or(AutoArmError::NothingPending)?;
+
+        if pending.postponed >= self.config.max_postpones {
+            return Err(AutoArmError::PostponeLimit { max_postpones: self.config.max_postpones });
+        }
+
+        pending.arm_at = pending.arm_at.checked_add_signed(ChronoDuration::seconds(secs as i64))
+            .ok_or(AutoArmError::InvalidPostpone)?;
+        pending.warned = false;
+        pending.postponed += 1;
+
+        println!("Auto-arm {} postponed to {}", pending.schedule, pending.arm_at);
+
+        events::publish(EventKind::AutoArmPostponed { schedule: pending.schedule.clone(), arm_at: pending.arm_at });
+
+        Ok(self.status())
+    }
+}
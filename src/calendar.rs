@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_RANGE_DAYS: i64 = 366;
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CalendarEntry {
+    Date { date: NaiveDate },
+    Range { from: NaiveDate, to: NaiveDate },
+    Yearly { month: u32, day: u32 },
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Calendar {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub entries: Vec<CalendarEntry>,
+}
+
+pub type Calendars = HashMap<String, Calendar>;
+
+impl CalendarEntry {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        match *self {
+            CalendarEntry::Date { date: entry_date } => entry_date == date,
+            CalendarEntry::Range { from, to } => from <= date && date <= to,
+            CalendarEntry::Yearly { month, day } => date.month() == month && date.day() == day,
+        }
+    }
+}
+
+impl Calendar {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.entries.iter().any(|entry| entry.contains(date))
+    }
+
+    /// Dates in `from..=to` that are in the calendar, never looking further than `MAX_RANGE_DAYS` ahead.
+    pub fn dates(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        let mut date = from;
+        let to = to.min(from + Duration::days(MAX_RANGE_DAYS - 1));
+
+        while date <= to {
+            if self.contains(date) {
+                dates.push(date);
+            }
+
+            date = date + Duration::days(1);
+        }
+
+        dates
+    }
+}
+
+/// Returns whether `date` passes the include/exclude calendar filters of a schedule.
+/// Unknown calendar names are treated as empty calendars.
+pub fn matches(calendars: &Calendars, include: &[String], exclude: &[String], date: NaiveDate) -> bool {
+    let in_calendar = |name: &String| calendars.get(name)
+        .map_or(false, |calendar| calendar.contains(date));
+
+    (include.is_empty() || include.iter().any(in_calendar)) && !exclude.iter().any(in_calendar)
+}
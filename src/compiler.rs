@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use actix::prelude::*;
+use chrono::{Duration, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::Calendars;
+use crate::relay::{ClearOutputCustomSchedule, ClearOutputDailySchedule, CustomEvent, DailyEvent, GetOutputCustomSchedule, GetOutputDailySchedule, RelayActor, SetOutputCustomSchedule, SetOutputDailySchedule};
+use crate::scheduler::{GetCalendars, GetCompilation, GetSchedules, Schedules, SchedulerActor, SetCompilation};
+
+const MAX_DAILY_EVENTS: usize = 10;
+const MAX_CUSTOM_EVENTS: usize = 10;
+
+
+#[derive(Deserialize)]
+pub struct CompileQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    pub window: Option<usize>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CompiledOutput {
+    pub daily: Vec<DailyEvent>,
+    pub custom: Vec<CustomEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Unsupported {
+    pub schedule: String,
+    pub reason: String,
+    #[serde(default)]
+    pub overflow: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum CompileError {
+    Unavailable,
+    Overflow { unsupported: Vec<Unsupported> },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Compilation {
+    pub compiled_at: NaiveDateTime,
+    pub window_days: usize,
+    pub dry_run: bool,
+    pub outputs: BTreeMap<usize, CompiledOutput>,
+    pub unsupported: Vec<Unsupported>,
+}
+
+#[derive(Serialize)]
+pub struct OutputMismatch {
+    pub output: usize,
+    pub missing_daily: Vec<DailyEvent>,
+    pub unexpected_daily: Vec<DailyEvent>,
+    pub missing_custom: Vec<CustomEvent>,
+    pub unexpected_custom: Vec<CustomEvent>,
+}
+
+#[derive(Serialize)]
+pub struct Verification {
+    pub compiled_at: NaiveDateTime,
+    pub matches: bool,
+    pub mismatches: Vec<OutputMismatch>,
+}
+
+fn difference<T: Clone + PartialEq>(left: &[T], right: &[T]) -> Vec<T> {
+    left.iter().filter(|item| !right.contains(item)).cloned().collect()
+}
+
+/// Only outputs that get at least one compiled event appear in the result, so relay schedules
+/// set by hand on other outputs are left alone.
+pub fn compile_schedules(schedules: &Schedules, calendars: &Calendars, outputs_number: usize, interlocked: &[usize],
+                         window_days: usize, now: NaiveDateTime) -> Compilation {
+    let mut outputs = BTreeMap::new();
+    let mut unsupported = Vec::new();
+    let mut names = schedules.keys().collect::<Vec<&String>>();
+
+    names.sort();
+
+    for name in names {
+        let schedule = &schedules[name];
+
+        if schedule.output == 0 || schedule.output > outputs_number {
+            unsupported.push(Unsupported {
+                schedule: name.clone(),
+                reason: format!("output {} does not exist on the relay", schedule.output),
+                overflow: false,
+            });
+            continue;
+        }
+
+        // The relay switches stored schedules itself, without the gateway's interlock checks.
+        if interlocked.contains(&schedule.output) {
+            unsupported.push(Unsupported {
+                schedule: name.clone(),
+                reason: format!("output {} is in an interlock group", schedule.output),
+                overflow: false,
+            });
+            continue;
+        }
+
+        if schedule.state > 1 {
+            unsupported.push(Unsupported {
+                schedule: name.clone(),
+                reason: format!("state {} cannot be stored on the relay", schedule.state),
+                overflow: false,
+            });
+            continue;
+        }
+
+        let output = outputs.entry(schedule.output).or_insert_with(CompiledOutput::default);
+
+        if schedule.days.is_empty() && schedule.include.is_empty() && schedule.exclude.is_empty() {
+            if output.daily.len() >= MAX_DAILY_EVENTS {
+                unsupported.push(Unsupported {
+                    schedule: name.clone(),
+                    reason: format!("more than {} daily events on output {}", MAX_DAILY_EVENTS, schedule.output),
+                    overflow: true,
+                });
+                continue;
+            }
+
+            output.daily.push(DailyEvent {
+                time: schedule.time.format("%H:%M:%S").to_string(),
+                state: schedule.state,
+            });
+            continue;
+        }
+
+        let mut overflow = false;
+
+        for day in 0..window_days {
+            let date = now.date() + Duration::days(day as i64);
+            let at = date.and_time(schedule.time);
+
+            if at <= now || !schedule.runs_on(calendars, date) {
+                continue;
+            }
+
+            if output.custom.len() >= MAX_CUSTOM_EVENTS {
+                overflow = true;
+                break;
+            }
+
+            output.custom.push(CustomEvent {
+                date_time: at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                state: schedule.state,
+            });
+        }
+
+        if overflow {
+            unsupported.push(Unsupported {
+                schedule: name.clone(),
+                reason: format!("more than {} custom events on output {} within {} days",
+                                MAX_CUSTOM_EVENTS, schedule.output, window_days),
+                overflow: true,
+            });
+        }
+    }
+
+    outputs.retain(|_, output: &mut CompiledOutput| !output.daily.is_empty() || !output.custom.is_empty());
+
+    for output in outputs.values_mut() {
+        output.daily.sort_by(|a, b| a.time.cmp(&b.time));
+        output.custom.sort_by(|a, b| a.date_time.cmp(&b.date_time));
+    }
+
+    Compilation {
+        compiled_at: now,
+        window_days,
+        dry_run: false,
+        outputs,
+        unsupported,
+    }
+}
+
+pub async fn compile(outputs_number: usize, interlocked: &[usize], window_days: usize,
+                     dry_run: bool) -> Result<Compilation, CompileError> {
+    let scheduler = SchedulerActor::from_registry();
+    let relay = RelayActor::from_registry();
+    let schedules = scheduler.send(GetSchedules).await.map_err(|_| CompileError::Unavailable)?;
+    let calendars = scheduler.send(GetCalendars).await.map_err(|_| CompileError::Unavailable)?;
+    let previous = scheduler.send(GetCompilation).await.map_err(|_| CompileError::Unavailable)?;
+    let now = Local::now().naive_local();
+
+    let mut compilation = compile_schedules(&schedules, &calendars, outputs_number, interlocked, window_days, now);
+
+    compilation.dry_run = dry_run;
+
+    if !dry_run {
+        // A truncated list would silently drop switching events, so nothing is written instead.
+        let overflow = compilation.unsupported.iter()
+            .filter(|unsupported| unsupported.overflow)
+            .cloned()
+            .collect::<Vec<Unsupported>>();
+
+        if !overflow.is_empty() {
+            println!("Compilation failed, {} schedules do not fit on the relay", overflow.len());
+
+            return Err(CompileError::Overflow { unsupported: overflow });
+        }
+
+        // Outputs compiled last time but no longer scheduled are cleared so stale events do not linger.
+        let stale = previous.iter()
+            .flat_map(|previous| previous.outputs.iter())
+            .filter(|(output, compiled)| !compilation.outputs.contains_key(output)
+                && (!compiled.daily.is_empty() || !compiled.custom.is_empty()))
+            .map(|(&output, _)| output)
+            .collect::<Vec<usize>>();
+
+        for output in stale {
+            relay.do_send(ClearOutputDailySchedule { number: output });
+            relay.do_send(ClearOutputCustomSchedule { number: output });
+        }
+
+        for (&output, compiled) in &compilation.outputs {
+            relay.do_send(ClearOutputDailySchedule { number: output });
+            relay.do_send(ClearOutputCustomSchedule { number: output });
+
+            for event in compiled.daily.iter().cloned() {
+                relay.do_send(SetOutputDailySchedule { number: output, event });
+            }
+
+            for event in compiled.custom.iter().cloned() {
+                relay.do_send(SetOutputCustomSchedule { number: output, event });
+            }
+        }
+
+        scheduler.do_send(SetCompilation(compilation.clone()));
+    }
+
+    Ok(compilation)
+}
+
+pub async fn verify() -> Result<Verification, ()> {
+    let relay = RelayActor::from_registry();
+    let compilation = SchedulerActor::from_registry().send(GetCompilation).await.map_err(|_| ())?.ok_or(())?;
+    let mut mismatches = Vec::new();
+
+    for (&output, compiled) in &compilation.outputs {
+        let daily = relay.send(GetOutputDailySchedule { number: output }).await.map_err(|_| ())??;
+        let custom = relay.send(GetOutputCustomSchedule { number: output }).await.map_err(|_| ())??;
+
+        let mismatch = OutputMismatch {
+            output,
+            missing_daily: difference(&compiled.daily, &daily),
+            unexpected_daily: difference(&daily, &compiled.daily),
+            missing_custom: difference(&compiled.custom, &custom),
+            unexpected_custom: difference(&custom, &compiled.custom),
+        };
+
+        if !(mismatch.missing_daily.is_empty() && mismatch.unexpected_daily.is_empty()
+            && mismatch.missing_custom.is_empty() && mismatch.unexpected_custom.is_empty()) {
+            mismatches.push(mismatch);
+        }
+    }
+
+    Ok(Verification {
+        compiled_at: compilation.compiled_at,
+        matches: mismatches.is_empty(),
+        mismatches,
+    })
+}
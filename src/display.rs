@@ -4,7 +4,7 @@ use std::time::Duration;
 
 use actix::prelude::*;
 use display_interface_spi::SPIInterfaceNoCS;
-use embedded_graphics::fonts::{Font24x32, Text};
+use embedded_graphics::fonts::{Font12x16, Text};
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::*;
@@ -13,12 +13,25 @@ use linux_embedded_hal::{Delay, Pin, Spidev};
 use linux_embedded_hal::spidev::{SPI_MODE_3, SpidevOptions};
 use st7789::{Orientation, ST7789};
 
+use crate::alarm::{AlarmActor, AlarmEvent, AlarmEventState, GetAlarmEvents, GetAlarmStatus};
+use crate::events::{Event, EventBusActor, EventKind, RegisterForEvents, UnregisterForEvents};
 use crate::relay;
 use crate::relay::{RelayStatus, RelayActor, RegisterForStatus, UnregisterForStatus};
+use crate::walk_test::WalkTestReport;
+use crate::zones::ZoneStatus;
+
+const ZONE_ROWS: usize = 8;
+const ZONE_ROW_HEIGHT: i32 = 24;
+const ALARM_ROWS: usize = 2;
 
 
 pub struct DisplayActor {
     pub id: usize,
+    pub events_id: usize,
+    alarms: Vec<AlarmEvent>,
+    zones: Vec<ZoneStatus>,
+    shutdown: Vec<usize>,
+    walk_test: Option<WalkTestReport>,
     display: Option<Mutex<ST7789<SPIInterfaceNoCS<Spidev, Pin>, Pin>>>,
 }
 
@@ -26,6 +39,11 @@ impl Default for DisplayActor {
     fn default() -> DisplayActor {
         Self {
             id: 0,
+            events_id: 0,
+            alarms: Vec::new(),
+            zones: Vec::new(),
+            shutdown: Vec::new(),
+            walk_test: None,
             display: None,
         }
     }
@@ -47,11 +65,48 @@ impl Actor for DisplayActor {
             })
             .wait(ctx);
 
+        EventBusActor::from_registry().send(RegisterForEvents(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.events_id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
         self.init_display(ctx);
+
+        AlarmActor::from_registry().send(GetAlarmEvents { state: Some(AlarmEventState::Raised) })
+            .into_actor(self)
+            .then(|res, act, _| {
+                if let Ok(res) = res {
+                    act.alarms = res;
+                    act.draw_alarms();
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        AlarmActor::from_registry().send(GetAlarmStatus)
+            .into_actor(self)
+            .then(|res, act, _| {
+                if let Ok(res) = res {
+                    act.shutdown = res.shutdown;
+                    act.draw_zones();
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+        EventBusActor::from_registry().do_send(UnregisterForEvents(self.events_id));
 
         Running::Stop
     }
@@ -85,49 +140,63 @@ impl DisplayActor {
         self.display = Some(Mutex::new(display));
     }
 
-    fn draw_status(&mut self, connected: bool, inputs: Vec<u32>) {
+    fn draw_zones(&mut self) {
         if let Some(ref mut val) = self.display {
             let display = &mut *val.lock().unwrap();
-            let text_style = TextStyle::new(Font24x32, Rgb565::WHITE);
+            let background_style = PrimitiveStyle::with_fill(Rgb565::BLACK);
             let inactive_style = PrimitiveStyle::with_fill(Rgb565::GREEN);
             let active_style = PrimitiveStyle::with_fill(Rgb565::RED);
+            let shutdown_style = PrimitiveStyle::with_fill(Rgb565::YELLOW);
+
+            for (index, zone) in self.zones.iter().take(ZONE_ROWS).enumerate() {
+                let top = index as i32 * ZONE_ROW_HEIGHT;
+                let shutdown = self.shutdown.contains(&zone.input);
+                let walk_test = self.walk_test.as_ref()
+                    .map(|report| report.seen.iter().any(|seen| seen.input == zone.input));
+                let label = if let Some(seen) = walk_test {
+                    format!("{} {}", zone.name, if seen { "seen" } else { "--" })
+                } else if shutdown {
+                    format!("{} shut down", zone.name)
+                } else {
+                    format!("{} {}", zone.name, if zone.open { "open" } else { "closed" })
+                };
+
+                let row = Rectangle::new(Point::new(0, top), Point::new(239, top + ZONE_ROW_HEIGHT - 1))
+                    .into_styled(background_style);
+
+                let circle = Circle::new(Point::new(10, top + ZONE_ROW_HEIGHT / 2), 7)
+                    .into_styled(if shutdown { shutdown_style } else if zone.open { active_style } else { inactive_style });
+
+                let text = Text::new(&label, Point::new(24, top + 4))
+                    .into_styled(TextStyle::new(Font12x16, if zone.open { Rgb565::RED } else { Rgb565::WHITE }));
+
+                row.draw(display).unwrap();
+                circle.draw(display).unwrap();
+                text.draw(display).unwrap();
+            }
+        }
+    }
 
-            let input1_circle =
-                Circle::new(Point::new(60, 60), 40)
-                    .into_styled(if inputs[0] == 0 { inactive_style } else { active_style });
-
-            let input1_text = Text::new("1", Point::new(50, 50))
-                .into_styled(text_style);
-
-            let input2_circle =
-                Circle::new(Point::new(180, 60), 40)
-                    .into_styled(if inputs[1] == 0 { inactive_style } else { active_style });
-
-            let input2_text = Text::new("2", Point::new(170, 50))
-                .into_styled(text_style);
-
-            let input3_circle =
-                Circle::new(Point::new(60, 180), 40)
-                    .into_styled(if inputs[2] == 0 { inactive_style } else { active_style });
-
-            let input3_text = Text::new("3", Point::new(50, 170))
-                .into_styled(text_style);
-
-            let input4_circle =
-                Circle::new(Point::new(180, 180), 40)
-                    .into_styled(if inputs[3] == 0 { inactive_style } else { active_style });
-
-            let input4_text = Text::new("4", Point::new(170, 170))
-                .into_styled(text_style);
-
-            input1_circle.draw(display).unwrap();
-            input2_circle.draw(display).unwrap();
-            input3_circle.draw(display).unwrap();
-            input4_circle.draw(display).unwrap();
-            input1_text.draw(display).unwrap();
-            input2_text.draw(display).unwrap();
-            input3_text.draw(display).unwrap();
-            input4_text.draw(display).unwrap();
+    fn draw_alarms(&mut self) {
+        if let Some(ref mut val) = self.display {
+            let display = &mut *val.lock().unwrap();
+            let top = ZONE_ROWS as i32 * ZONE_ROW_HEIGHT;
+            let background_style = PrimitiveStyle::with_fill(if self.alarms.is_empty() { Rgb565::BLACK } else { Rgb565::RED });
+
+            Rectangle::new(Point::new(0, top), Point::new(239, 239))
+                .into_styled(background_style)
+                .draw(display)
+                .unwrap();
+
+            // Newest unacknowledged alarms first; they stay on screen until acknowledged.
+            for (index, alarm) in self.alarms.iter().rev().take(ALARM_ROWS).enumerate() {
+                let label = format!("#{} {}", alarm.id, alarm.zone.as_deref().unwrap_or("ALARM"));
+
+                Text::new(&label, Point::new(4, top + index as i32 * ZONE_ROW_HEIGHT + 4))
+                    .into_styled(TextStyle::new(Font12x16, Rgb565::WHITE))
+                    .draw(display)
+                    .unwrap();
+            }
         }
     }
 }
@@ -136,6 +205,41 @@ impl Handler<RelayStatus> for DisplayActor {
     type Result = ();
 
     fn handle(&mut self, message: RelayStatus, ctx: &mut Self::Context) -> Self::Result {
-        self.draw_status(message.connected, message.inputs);
+        if let Some(zones) = message.zones {
+            self.zones = zones;
+            self.draw_zones();
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Handler<Event> for DisplayActor {
+    type Result = ();
+
+    fn handle(&mut self, message: Event, ctx: &mut Self::Context) -> Self::Result {
+        match message.kind {
+            EventKind::AlarmEventChanged { alarm } => {
+                self.alarms.retain(|event| event.id != alarm.id);
+
+                if alarm.state == AlarmEventState::Raised {
+                    self.alarms.push(alarm);
+                }
+
+                self.draw_alarms();
+            }
+            EventKind::AlarmStateChanged { status } => {
+                self.shutdown = status.shutdown;
+                self.walk_test = None;
+                self.draw_zones();
+            }
+            EventKind::ZoneShutdown { input, .. } => {
+                self.shutdown.push(input);
+                self.draw_zones();
+            }
+            EventKind::WalkTestUpdated { report } => {
+                self.walk_test = Some(report);
+                self.draw_zones();
+            }
+            _ => (),
+        }
+    }
+}
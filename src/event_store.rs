@@ -0,0 +1,597 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Duration as ChronoDuration, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::Duration;
+
+use crate::events::{Event, EventBusActor, RegisterForRestrictedEvents, UnregisterForEvents};
+use crate::relay::{RegisterForStatus, RelayActor, RelayStatus, UnregisterForStatus};
+use crate::storage;
+
+const EVENTS_FILE: &str = "events.ndjson";
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+
+fn default_max_events() -> usize {
+    20000
+}
+
+fn default_max_age_days() -> i64 {
+    90
+}
+
+fn default_history_max_events() -> usize {
+    100000
+}
+
+fn default_history_max_age_days() -> i64 {
+    30
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct EventStoreConfig {
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: i64,
+    /// Input and output changes are far more frequent than API and alarm events, so they are kept separately.
+    #[serde(default = "default_history_max_events")]
+    pub history_max_events: usize,
+    #[serde(default = "default_history_max_age_days")]
+    pub history_max_age_days: i64,
+}
+
+impl Default for EventStoreConfig {
+    fn default() -> Self {
+        EventStoreConfig {
+            max_events: default_max_events(),
+            max_age_days: default_max_age_days(),
+            history_max_events: default_history_max_events(),
+            history_max_age_days: default_history_max_age_days(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct StoredEvent {
+    pub id: u64,
+    pub time: NaiveDateTime,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub channel: Option<usize>,
+    pub actor: Option<String>,
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub restricted: bool,
+}
+
+#[derive(Deserialize)]
+pub struct EventQuery {
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    pub channel: Option<usize>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct EventPage {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub events: Vec<StoredEvent>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    Input,
+    Output,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Csv
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub kind: Option<ChannelKind>,
+    pub channel: Option<usize>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Serialize)]
+pub struct HistorySegment {
+    pub kind: ChannelKind,
+    pub channel: usize,
+    pub on: bool,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub duration_secs: i64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelSummary {
+    pub kind: ChannelKind,
+    pub channel: usize,
+    pub on_count: u32,
+    pub on_secs: i64,
+    pub off_secs: i64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Record {
+    pub event_type: String,
+    pub channel: Option<usize>,
+    pub actor: Option<String>,
+    pub data: Value,
+}
+
+#[derive(Message)]
+#[rtype(result = "EventPage")]
+pub struct QueryEvents {
+    pub query: EventQuery,
+    pub restricted: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<HistorySegment>")]
+pub struct GetHistory(pub HistoryQuery);
+
+pub struct EventStoreActor {
+    id: usize,
+    events_id: usize,
+    data_dir: String,
+    config: EventStoreConfig,
+    events: VecDeque<StoredEvent>,
+    next_id: u64,
+    inputs: Vec<u32>,
+    outputs: Vec<u32>,
+    connected: Option<bool>,
+}
+
+impl Default for EventStoreActor {
+    fn default() -> Self {
+        EventStoreActor {
+            id: 0,
+            events_id: 0,
+            data_dir: "data".to_string(),
+            config: EventStoreConfig::default(),
+            events: VecDeque::new(),
+            next_id: 1,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            connected: None,
+        }
+    }
+}
+
+impl Actor for EventStoreActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.events = storage::load_lines::<StoredEvent>(&self.events_path()).into();
+        self.next_id = self.events.back().map_or(1, |event| event.id + 1);
+        self.apply_retention();
+
+        // States from before a restart are unknown, so the history must not carry them across the downtime.
+        self.store("history_started", None, Some("system".to_string()), json!({}));
+
+        RelayActor::from_registry().send(RegisterForStatus(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        EventBusActor::from_registry().send(RegisterForRestrictedEvents(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.events_id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        ctx.run_interval(RETENTION_INTERVAL, |act, _| act.apply_retention());
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+        EventBusActor::from_registry().do_send(UnregisterForEvents(self.events_id));
+
+        Running::Stop
+    }
+}
+
+impl Supervised for EventStoreActor {}
+
+impl SystemService for EventStoreActor {}
+
+pub fn record(event_type: &str, channel: Option<usize>, actor: Option<String>, data: Value) {
+    EventStoreActor::from_registry().do_send(Record {
+        event_type: event_type.to_string(),
+        channel,
+        actor,
+        data,
+    });
+}
+
+impl ChannelKind {
+    fn from_event_type(event_type: &str) -> Option<ChannelKind> {
+        match event_type {
+            "input_changed" => Some(ChannelKind::Input),
+            "output_changed" => Some(ChannelKind::Output),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ChannelKind::Input => "input",
+            ChannelKind::Output => "output",
+        }
+    }
+
+    fn event_type(self) -> &'static str {
+        match self {
+            ChannelKind::Input => "input_changed",
+            ChannelKind::Output => "output_changed",
+        }
+    }
+}
+
+fn is_history(event: &StoredEvent) -> bool {
+    ChannelKind::from_event_type(&event.event_type).is_some()
+        || ["relay_states", "history_started", "connection_changed"].contains(&event.event_type.as_str())
+}
+
+impl HistoryQuery {
+    fn matches(&self, kind: ChannelKind, channel: usize) -> bool {
+        self.kind.map_or(true, |expected| expected == kind)
+            && self.channel.map_or(true, |expected| expected == channel)
+    }
+}
+
+impl HistorySegment {
+    pub const CSV_HEADER: &'static str = "kind,channel,state,start,end,duration_secs";
+
+    pub fn csv_row(&self) -> String {
+        format!("{},{},{},{},{},{}", self.kind.name(), self.channel, if self.on { "on" } else { "off" },
+                self.start.format(TIME_FORMAT), self.end.format(TIME_FORMAT), self.duration_secs)
+    }
+}
+
+impl ChannelSummary {
+    pub const CSV_HEADER: &'static str = "kind,channel,on_count,on_secs,off_secs";
+
+    pub fn csv_row(&self) -> String {
+        format!("{},{},{},{},{}", self.kind.name(), self.channel, self.on_count, self.on_secs, self.off_secs)
+    }
+}
+
+pub fn summarize(segments: &[HistorySegment]) -> Vec<ChannelSummary> {
+    let mut summary: HashMap<(ChannelKind, usize), ChannelSummary> = HashMap::new();
+
+    for segment in segments {
+        let entry = summary.entry((segment.kind, segment.channel)).or_insert(ChannelSummary {
+            kind: segment.kind,
+            channel: segment.channel,
+            on_count: 0,
+            on_secs: 0,
+            off_secs: 0,
+        });
+
+        if segment.on {
+            entry.on_count += 1;
+            entry.on_secs += segment.duration_secs;
+        } else {
+            entry.off_secs += segment.duration_secs;
+        }
+    }
+
+    let mut summary = summary.into_iter().map(|(_, summary)| summary).collect::<Vec<ChannelSummary>>();
+
+    summary.sort_by_key(|summary| (summary.kind, summary.channel));
+    summary
+}
+
+fn close_segment(segments: &mut Vec<HistorySegment>, kind: ChannelKind, channel: usize, on: bool,
+                 start: NaiveDateTime, end: NaiveDateTime) {
+    if end > start {
+        segments.push(HistorySegment {
+            kind,
+            channel,
+            on,
+            start,
+            end,
+            duration_secs: (end - start).num_seconds(),
+        });
+    }
+}
+
+fn change_segment(open: &mut HashMap<(ChannelKind, usize), (bool, NaiveDateTime)>, segments: &mut Vec<HistorySegment>,
+                  kind: ChannelKind, channel: usize, on: bool, time: NaiveDateTime, from: NaiveDateTime) {
+    match open.get(&(kind, channel)) {
+        Some(&(state, _)) if state == on => return,
+        Some(&(state, since)) => close_segment(segments, kind, channel, state, since.max(from), time.max(from)),
+        None => (),
+    }
+
+    open.insert((kind, channel), (on, time));
+}
+
+impl EventQuery {
+    fn matches(&self, event: &StoredEvent) -> bool {
+        self.event_type.as_ref().map_or(true, |types| types.split(',').any(|event_type| event_type == event.event_type))
+            && self.channel.map_or(true, |channel| event.channel == Some(channel))
+            && self.from.map_or(true, |from| event.time >= from)
+            && self.to.map_or(true, |to| event.time < to)
+    }
+}
+
+impl EventStoreActor {
+    pub fn new(data_dir: &str, config: EventStoreConfig) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            config,
+            ..EventStoreActor::default()
+        }
+    }
+
+    fn events_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, EVENTS_FILE)
+    }
+
+    fn store(&mut self, event_type: &str, channel: Option<usize>, actor: Option<String>, data: Value) {
+        self.store_event(event_type, channel, actor, data, false);
+    }
+
+    fn store_event(&mut self, event_type: &str, channel: Option<usize>, actor: Option<String>, data: Value, restricted: bool) {
+        let event = StoredEvent {
+            id: self.next_id,
+            time: Local::now().naive_local(),
+            event_type: event_type.to_string(),
+            channel,
+            actor,
+            data,
+            restricted,
+        };
+
+        self.next_id += 1;
+
+        storage::append_line(&self.events_path(), &event);
+        self.events.push_back(event);
+
+        // Trim in batches so the file is not rewritten on every event once the limit is reached.
+        let limit = self.config.max_events + self.config.history_max_events;
+
+        if self.events.len() > limit + limit / 10 {
+            self.apply_retention();
+        }
+    }
+
+    fn apply_retention(&mut self) {
+        let now = Local::now().naive_local();
+        let oldest = now - ChronoDuration::days(self.config.max_age_days);
+        let oldest_history = now - ChronoDuration::days(self.config.history_max_age_days);
+        let before = self.events.len();
+        let history = self.events.iter().filter(|event| is_history(event)).count();
+        let mut excess = (before - history).saturating_sub(self.config.max_events);
+        let mut excess_history = history.saturating_sub(self.config.history_max_events);
+
+        // Events are in time order, so the first ones over a limit are the oldest.
+        self.events.retain(|event| {
+            let (excess, oldest) = if is_history(event) {
+                (&mut excess_history, oldest_history)
+            } else {
+                (&mut excess, oldest)
+            };
+
+            if *excess > 0 {
+                *excess -= 1;
+
+                return false;
+            }
+
+            event.time >= oldest
+        });
+
+        if self.events.len() != before {
+            println!("Event store: removed {} expired events", before - self.events.len());
+
+            storage::save_lines(&self.events_path(), &self.events);
+        }
+    }
+
+    // Rebuilds on/off segments from the recorded changes, clipped to the requested range.
+    // A lost relay connection or a restart ends every segment since the states are unknown until it is back.
+    fn history(&self, query: &HistoryQuery) -> Vec<HistorySegment> {
+        let now = Local::now().naive_local();
+        let from = query.from.unwrap_or_else(|| self.events.front().map_or(now, |event| event.time));
+        let to = query.to.map_or(now, |to| to.min(now));
+        let mut open: HashMap<(ChannelKind, usize), (bool, NaiveDateTime)> = HashMap::new();
+        let mut segments = Vec::new();
+
+        for event in self.events.iter().take_while(|event| event.time < to) {
+            if event.event_type == "connection_changed" || event.event_type == "history_started" {
+                for ((kind, channel), (on, since)) in open.drain() {
+                    close_segment(&mut segments, kind, channel, on, since.max(from), event.time.max(from));
+                }
+
+                continue;
+            }
+
+            if event.event_type == "relay_states" {
+                let kind = event.data.get("kind").cloned().and_then(|kind| serde_json::from_value::<ChannelKind>(kind).ok());
+                let states = event.data.get("states").and_then(Value::as_array);
+
+                if let (Some(kind), Some(states)) = (kind, states) {
+                    for (index, state) in states.iter().enumerate() {
+                        if query.matches(kind, index + 1) {
+                            let on = state.as_u64().map_or(false, |state| state != 0);
+
+                            change_segment(&mut open, &mut segments, kind, index + 1, on, event.time, from);
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            let (kind, channel) = match (ChannelKind::from_event_type(&event.event_type), event.channel) {
+                (Some(kind), Some(channel)) if query.matches(kind, channel) => (kind, channel),
+                _ => continue,
+            };
+            let on = event.data.get("state").and_then(Value::as_u64).map_or(false, |state| state != 0);
+
+            change_segment(&mut open, &mut segments, kind, channel, on, event.time, from);
+        }
+
+        for ((kind, channel), (on, since)) in open {
+            close_segment(&mut segments, kind, channel, on, since.max(from), to);
+        }
+
+        segments.sort_by_key(|segment| (segment.start, segment.kind, segment.channel));
+        segments
+    }
+
+    // With no previous states, after a start or a reconnect, all channels are recorded as one snapshot,
+    // giving the history a known starting point without an event per channel.
+    fn record_changes(&mut self, kind: ChannelKind, previous: &[u32], current: &[u32]) {
+        if previous.is_empty() {
+            if !current.is_empty() {
+                self.store("relay_states", None, Some("relay".to_string()), json!({ "kind": kind, "states": current }));
+            }
+
+            return;
+        }
+
+        for (index, &state) in current.iter().enumerate() {
+            if previous.get(index) != Some(&state) {
+                self.store(kind.event_type(), Some(index + 1), Some("relay".to_string()), json!({ "state": state }));
+            }
+        }
+    }
+}
+
+impl Handler<RelayStatus> for EventStoreActor {
+    type Result = ();
+
+    fn handle(&mut self, message: RelayStatus, _: &mut Self::Context) -> Self::Result {
+        if self.connected != Some(message.connected) {
+            if self.connected.is_some() {
+                self.store("connection_changed", None, Some("relay".to_string()), json!({ "connected": message.connected }));
+            }
+
+            self.connected = Some(message.connected);
+
+            if !message.connected {
+                self.inputs.clear();
+                self.outputs.clear();
+            }
+        }
+
+        if let Some(inputs) = message.inputs {
+            let previous = std::mem::replace(&mut self.inputs, inputs.clone());
+
+            self.record_changes(ChannelKind::Input, &previous, &inputs);
+        }
+
+        if let Some(outputs) = message.outputs {
+            let previous = std::mem::replace(&mut self.outputs, outputs.clone());
+
+            self.record_changes(ChannelKind::Output, &previous, &outputs);
+        }
+    }
+}
+
+impl Handler<Event> for EventStoreActor {
+    type Result = ();
+
+    fn handle(&mut self, message: Event, _: &mut Self::Context) -> Self::Result {
+        let restricted = message.kind.is_restricted();
+        let mut data = json!(message.kind);
+        let event_type = data.get("type").and_then(Value::as_str).unwrap_or("event").to_string();
+        let channel = data.get("output").or_else(|| data.get("input"))
+            .and_then(Value::as_u64)
+            .map(|channel| channel as usize);
+
+        if let Some(data) = data.as_object_mut() {
+            data.remove("type");
+        }
+
+        self.store_event(&event_type, channel, Some("system".to_string()), data, restricted);
+    }
+}
+
+impl Handler<Record> for EventStoreActor {
+    type Result = ();
+
+    fn handle(&mut self, message: Record, _: &mut Context<Self>) -> Self::Result {
+        self.store(&message.event_type, message.channel, message.actor, message.data);
+    }
+}
+
+impl Handler<QueryEvents> for EventStoreActor {
+    type Result = MessageResult<QueryEvents>;
+
+    fn handle(&mut self, QueryEvents { query, restricted }: QueryEvents, _: &mut Context<Self>) -> Self::Result {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let matching = self.events.iter()
+            .rev()
+            .filter(|event| restricted || !event.restricted)
+            .filter(|event| query.matches(event))
+            .collect::<Vec<&StoredEvent>>();
+
+        MessageResult(EventPage {
+            total: matching.len(),
+            offset: query.offset,
+            limit,
+            events: matching.into_iter()
+                .skip(query.offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+impl Handler<GetHistory> for EventStoreActor {
+    type Result = MessageResult<GetHistory>;
+
+    fn handle(&mut self, GetHistory(query): GetHistory, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.history(&query))
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Local, NaiveDateTime};
+use rand::prelude::*;
+use serde::Serialize;
+
+use crate::ajax::HubStatus;
+use crate::alarm::{AlarmEvent, AlarmStatus};
+use crate::presence::PresenceState;
+use crate::walk_test::WalkTestReport;
+use crate::zones::ZoneStatus;
+
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    OutputCutoff { output: usize, max_on_secs: u64 },
+    RuleNotification { rule: String, message: String },
+    AlarmStateChanged { status: AlarmStatus },
+    Chime { zone: String },
+    SirenCutoff { cutoff_secs: u64 },
+    Duress { user: String },
+    AlarmEventChanged { alarm: AlarmEvent },
+    PendingVerification { zone: String, input: usize, window_secs: u64 },
+    VerificationExpired { zone: String, input: usize },
+    ZoneShutdown { zone: String, input: usize, alarms: u32 },
+    WalkTestUpdated { report: WalkTestReport },
+    AutoArmWarning { schedule: String, arm_at: NaiveDateTime },
+    AutoArmPostponed { schedule: String, arm_at: NaiveDateTime },
+    AutoArmFailed { schedule: String, open_zones: Vec<ZoneStatus> },
+    PresenceChanged { device: String, state: PresenceState },
+    PresenceNotification { message: String },
+    HubStateChanged { hub: HubStatus },
+}
+
+#[derive(Clone, Message, Serialize)]
+#[rtype(result = "()")]
+pub struct Event {
+    pub time: NaiveDateTime,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Publish(pub EventKind);
+
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct RegisterForEvents(pub Recipient<Event>);
+
+/// Subscribes to all events, including restricted ones that must not reach panels or WebSocket clients.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct RegisterForRestrictedEvents(pub Recipient<Event>);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnregisterForEvents(pub usize);
+
+pub struct EventBusActor {
+    rng: ThreadRng,
+    clients: HashMap<usize, (Recipient<Event>, bool)>,
+}
+
+impl Default for EventBusActor {
+    fn default() -> Self {
+        EventBusActor {
+            rng: thread_rng(),
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for EventBusActor {
+    type Context = Context<Self>;
+}
+
+impl Supervised for EventBusActor {}
+
+impl SystemService for EventBusActor {}
+
+impl EventKind {
+    /// A duress disarm must stay invisible to whoever is standing at the panel.
+    pub fn is_restricted(&self) -> bool {
+        matches!(self, EventKind::Duress { .. })
+    }
+}
+
+pub fn publish(kind: EventKind) {
+    EventBusActor::from_registry().do_send(Publish(kind));
+}
+
+impl Handler<Publish> for EventBusActor {
+    type Result = ();
+
+    fn handle(&mut self, Publish(kind): Publish, _: &mut Context<Self>) -> Self::Result {
+        let event = Event {
+            time: Local::now().naive_local(),
+            kind,
+        };
+
+        let restricted = event.kind.is_restricted();
+
+        for (client, _) in self.clients.values().filter(|(_, privileged)| *privileged || !restricted) {
+            if client.do_send(event.clone()).is_err() {
+                println!("Unable to send event to subscription");
+            }
+        }
+    }
+}
+
+impl Handler<RegisterForEvents> for EventBusActor {
+    type Result = usize;
+
+    fn handle(&mut self, RegisterForEvents(client): RegisterForEvents, _: &mut Context<Self>) -> Self::Result {
+        let id = self.rng.gen::<usize>();
+
+        self.clients.insert(id, (client, false));
+
+        id
+    }
+}
+
+impl Handler<RegisterForRestrictedEvents> for EventBusActor {
+    type Result = usize;
+
+    fn handle(&mut self, RegisterForRestrictedEvents(client): RegisterForRestrictedEvents, _: &mut Context<Self>) -> Self::Result {
+        let id = self.rng.gen::<usize>();
+
+        self.clients.insert(id, (client, true));
+
+        id
+    }
+}
+
+impl Handler<UnregisterForEvents> for EventBusActor {
+    type Result = ();
+
+    fn handle(&mut self, UnregisterForEvents(id): UnregisterForEvents, _: &mut Context<Self>) {
+        self.clients.remove(&id);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::relay::{CustomEvent, DailyEvent, GetOutputCustomSchedule, GetOutputDailySchedule, RelayActor, SetOutputCustomSchedule, SetOutputDailySchedule};
+use crate::scheduler::{GetSchedules, Schedule, SchedulerActor, SetSchedule};
+
+const PRODUCT_ID: &str = "-//Skyggedans//ajax_alarm//EN";
+const OUTPUT_PROPERTY: &str = "X-AJAX-OUTPUT";
+const STATE_PROPERTY: &str = "X-AJAX-STATE";
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT: &str = "%Y%m%d";
+
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    pub output: Option<usize>,
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImportAction {
+    Daily { output: usize, event: DailyEvent },
+    Custom { output: usize, event: CustomEvent },
+    Gateway { name: String, schedule: Schedule },
+}
+
+#[derive(Serialize)]
+pub struct SkippedEvent {
+    pub uid: String,
+    pub summary: String,
+    pub reason: String,
+}
+
+#[derive(Default, Serialize)]
+pub struct ImportDiff {
+    pub dry_run: bool,
+    pub add: Vec<ImportAction>,
+    pub unchanged: Vec<ImportAction>,
+    pub skipped: Vec<SkippedEvent>,
+}
+
+#[derive(Default)]
+struct VEvent {
+    properties: HashMap<String, (HashMap<String, String>, String)>,
+}
+
+enum Recurrence {
+    Once,
+    Daily,
+    Weekly(Vec<u32>),
+}
+
+impl VEvent {
+    fn value(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(|(_, value)| value.as_str())
+    }
+
+    fn date_time(&self, name: &str) -> Result<Option<NaiveDateTime>, String> {
+        match self.properties.get(name) {
+            Some((params, value)) => parse_date_time(params, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn recurrence(&self) -> Result<Recurrence, String> {
+        let rule = match self.value("RRULE") {
+            Some(rule) => rule,
+            None => return Ok(Recurrence::Once),
+        };
+
+        let parts = rule.split(';')
+            .filter_map(|part| {
+                let mut split = part.splitn(2, '=');
+
+                Some((split.next()?.to_uppercase(), split.next()?.to_uppercase()))
+            })
+            .collect::<HashMap<String, String>>();
+
+        for key in parts.keys() {
+            match key.as_str() {
+                "FREQ" | "BYDAY" | "WKST" => (),
+                "INTERVAL" if parts[key] == "1" => (),
+                _ => return Err(format!("unsupported RRULE part {}", key)),
+            }
+        }
+
+        match parts.get("FREQ").map(String::as_str) {
+            Some("DAILY") if !parts.contains_key("BYDAY") => Ok(Recurrence::Daily),
+            Some("DAILY") | Some("WEEKLY") => {
+                let days = match parts.get("BYDAY") {
+                    Some(days) => days.split(',').map(parse_weekday).collect::<Result<Vec<u32>, String>>()?,
+                    None => Vec::new(),
+                };
+
+                Ok(if days.is_empty() || days.len() == 7 { Recurrence::Daily } else { Recurrence::Weekly(days) })
+            }
+            Some(freq) => Err(format!("unsupported RRULE frequency {}", freq)),
+            None => Err("RRULE without FREQ".to_string()),
+        }
+    }
+}
+
+fn parse_weekday(day: &str) -> Result<u32, String> {
+    match day {
+        "MO" => Ok(1),
+        "TU" => Ok(2),
+        "WE" => Ok(3),
+        "TH" => Ok(4),
+        "FR" => Ok(5),
+        "SA" => Ok(6),
+        "SU" => Ok(7),
+        _ => Err(format!("unsupported BYDAY value {}", day)),
+    }
+}
+
+fn parse_date_time(params: &HashMap<String, String>, value: &str) -> Result<NaiveDateTime, String> {
+    if params.get("VALUE").map(String::as_str) == Some("DATE") || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, DATE_FORMAT)
+            .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+            .map_err(|err| format!("invalid date {}: {}", value, err));
+    }
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, DATE_TIME_FORMAT)
+            .map(|date_time| Utc.from_utc_datetime(&date_time).with_timezone(&Local).naive_local())
+            .map_err(|err| format!("invalid date-time {}: {}", value, err));
+    }
+
+    NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT)
+        .map_err(|err| format!("invalid date-time {}: {}", value, err))
+}
+
+fn parse(text: &str) -> Vec<VEvent> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+            }
+        } else {
+            lines.push(line.trim_end().to_string());
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<VEvent> = None;
+
+    for line in lines {
+        let (head, value) = match line.find(':') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => continue,
+        };
+
+        let mut head_parts = head.split(';');
+        let name = head_parts.next().unwrap_or("").to_uppercase();
+
+        let params = head_parts
+            .filter_map(|param| {
+                let mut split = param.splitn(2, '=');
+
+                Some((split.next()?.to_uppercase(), split.next()?.to_string()))
+            })
+            .collect::<HashMap<String, String>>();
+
+        match (name.as_str(), value) {
+            ("BEGIN", "VEVENT") => current = Some(VEvent::default()),
+            ("END", "VEVENT") => events.extend(current.take()),
+            _ => if let Some(ref mut event) = current {
+                event.properties.insert(name, (params, value.to_string()));
+            },
+        }
+    }
+
+    events
+}
+
+fn event_actions(event: &VEvent, default_output: Option<usize>, interlocked: &[usize]) -> Result<Vec<ImportAction>, String> {
+    let output = match event.value(OUTPUT_PROPERTY) {
+        Some(output) => output.trim().parse::<usize>().map_err(|_| format!("invalid {}", OUTPUT_PROPERTY))?,
+        None => default_output.ok_or_else(|| format!("no {} property and no output given", OUTPUT_PROPERTY))?,
+    };
+
+    // Exceptions and time zones cannot be expressed in relay or gateway schedules, so such events
+    // are reported instead of being imported at the wrong times.
+    for name in &["EXDATE", "RDATE", "RECURRENCE-ID"] {
+        if event.properties.contains_key(*name) {
+            return Err(format!("{} is not supported", name));
+        }
+    }
+
+    for name in &["DTSTART", "DTEND"] {
+        if let Some(zone) = event.properties.get(*name).and_then(|(params, _)| params.get("TZID")) {
+            return Err(format!("{} in time zone {} is not supported", name, zone));
+        }
+    }
+
+    let start = event.date_time("DTSTART")?.ok_or_else(|| "no DTSTART".to_string())?;
+    let recurrence = event.recurrence()?;
+    let uid = event.value("UID").unwrap_or("event").to_string();
+
+    // Relay schedules would switch an interlocked output without the interlock.
+    if interlocked.contains(&output) && !matches!(recurrence, Recurrence::Weekly(_)) {
+        return Err(format!("output {} is interlocked and cannot use relay schedules", output));
+    }
+
+    let points = match event.value(STATE_PROPERTY) {
+        Some(state) => vec![(start, state.trim().parse::<u32>().map_err(|_| format!("invalid {}", STATE_PROPERTY))?)],
+        None => {
+            let mut points = vec![(start, 1)];
+
+            if let Some(end) = event.date_time("DTEND")? {
+                if end.date() != start.date() && !matches!(recurrence, Recurrence::Once) {
+                    return Err("recurring events spanning midnight are not supported".to_string());
+                }
+
+                points.push((end, 0));
+            }
+
+            points
+        }
+    };
+
+    Ok(points.into_iter()
+        .map(|(date_time, state)| match recurrence {
+            Recurrence::Once => ImportAction::Custom {
+                output,
+                event: CustomEvent {
+                    date_time: date_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    state,
+                },
+            },
+            Recurrence::Daily => ImportAction::Daily {
+                output,
+                event: DailyEvent {
+                    time: date_time.format("%H:%M:%S").to_string(),
+                    state,
+                },
+            },
+            Recurrence::Weekly(ref days) => ImportAction::Gateway {
+                name: format!("ical-{}-{}", uid, if state == 0 { "off" } else { "on" }),
+                schedule: Schedule {
+                    output,
+                    time: date_time.time(),
+                    state,
+                    days: days.clone(),
+                    include: Vec::new(),
+                    exclude: Vec::new(),
+                },
+            },
+        })
+        .collect())
+}
+
+fn state_name(state: u32) -> &'static str {
+    if state == 0 { "off" } else { "on" }
+}
+
+fn write_event(out: &mut String, uid: String, summary: String, start: NaiveDateTime, rule: Option<&str>,
+               output: usize, state: u32) {
+    out.push_str("BEGIN:VEVENT\r\n");
+    out.push_str(&format!("UID:{}\r\n", uid));
+    out.push_str(&format!("DTSTAMP:{}Z\r\n", Utc::now().format(DATE_TIME_FORMAT)));
+    out.push_str(&format!("DTSTART:{}\r\n", start.format(DATE_TIME_FORMAT)));
+
+    if let Some(rule) = rule {
+        out.push_str(&format!("RRULE:{}\r\n", rule));
+    }
+
+    out.push_str(&format!("SUMMARY:{}\r\n", summary));
+    out.push_str(&format!("{}:{}\r\n", OUTPUT_PROPERTY, output));
+    out.push_str(&format!("{}:{}\r\n", STATE_PROPERTY, state));
+    out.push_str("END:VEVENT\r\n");
+}
+
+pub async fn export(outputs_number: usize) -> Result<String, ()> {
+    let relay = RelayActor::from_registry();
+    let today = Local::now().date_naive();
+    let mut out = String::new();
+
+    out.push_str("BEGIN:VCALENDAR\r\n");
+    out.push_str("VERSION:2.0\r\n");
+    out.push_str(&format!("PRODID:{}\r\n", PRODUCT_ID));
+
+    for output in 1..=outputs_number {
+        let daily = relay.send(GetOutputDailySchedule { number: output }).await.map_err(|_| ())??;
+
+        for (index, event) in daily.iter().enumerate() {
+            let time = NaiveTime::parse_from_str(&event.time, "%H:%M:%S").map_err(|_| ())?;
+
+            write_event(&mut out, format!("output-{}-daily-{}@ajax_alarm", output, index),
+                        format!("Output {} {}", output, state_name(event.state)),
+                        today.and_time(time), Some("FREQ=DAILY"), output, event.state);
+        }
+
+        let custom = relay.send(GetOutputCustomSchedule { number: output }).await.map_err(|_| ())??;
+
+        for (index, event) in custom.iter().enumerate() {
+            let date_time = NaiveDateTime::parse_from_str(&event.date_time, "%Y-%m-%d %H:%M:%S").map_err(|_| ())?;
+
+            write_event(&mut out, format!("output-{}-custom-{}@ajax_alarm", output, index),
+                        format!("Output {} {}", output, state_name(event.state)),
+                        date_time, None, output, event.state);
+        }
+    }
+
+    out.push_str("END:VCALENDAR\r\n");
+
+    Ok(out)
+}
+
+pub async fn import(text: &str, query: ImportQuery, interlocked: &[usize]) -> Result<ImportDiff, ()> {
+    let relay = RelayActor::from_registry();
+    let scheduler = SchedulerActor::from_registry();
+    let mut diff = ImportDiff { dry_run: query.dry_run, ..ImportDiff::default() };
+    let mut actions = Vec::new();
+
+    for event in parse(text) {
+        match event_actions(&event, query.output, interlocked) {
+            Ok(event_actions) => actions.extend(event_actions),
+            Err(reason) => diff.skipped.push(SkippedEvent {
+                uid: event.value("UID").unwrap_or("").to_string(),
+                summary: event.value("SUMMARY").unwrap_or("").to_string(),
+                reason,
+            }),
+        }
+    }
+
+    let mut daily: HashMap<usize, Vec<DailyEvent>> = HashMap::new();
+    let mut custom: HashMap<usize, Vec<CustomEvent>> = HashMap::new();
+    let gateway = scheduler.send(GetSchedules).await.map_err(|_| ())?;
+
+    for action in actions {
+        let exists = match action {
+            ImportAction::Daily { output, ref event } => {
+                if !daily.contains_key(&output) {
+                    daily.insert(output, relay.send(GetOutputDailySchedule { number: output }).await.map_err(|_| ())??);
+                }
+
+                daily[&output].contains(event)
+            }
+            ImportAction::Custom { output, ref event } => {
+                if !custom.contains_key(&output) {
+                    custom.insert(output, relay.send(GetOutputCustomSchedule { number: output }).await.map_err(|_| ())??);
+                }
+
+                custom[&output].contains(event)
+            }
+            ImportAction::Gateway { ref name, ref schedule } => gateway.get(name) == Some(schedule),
+        };
+
+        if exists || diff.add.contains(&action) {
+            diff.unchanged.push(action);
+        } else {
+            diff.add.push(action);
+        }
+    }
+
+    if !query.dry_run {
+        for action in diff.add.iter().cloned() {
+            match action {
+                ImportAction::Daily { output, event } =>
+                    relay.do_send(SetOutputDailySchedule { number: output, event }),
+                ImportAction::Custom { output, event } =>
+                    relay.do_send(SetOutputCustomSchedule { number: output, event }),
+                ImportAction::Gateway { name, schedule } =>
+                    scheduler.do_send(SetSchedule { name, schedule }),
+            }
+        }
+    }
+
+    Ok(diff)
+}
@@ -1,8 +1,10 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
+use std::collections::HashMap;
 use std::env;
-use std::io::{self, Write};
+use std::fs::File;
+use std::io::{self, BufReader, Write};
 use std::process;
 
 use actix::prelude::*;
@@ -11,9 +13,11 @@ use actix_files as fs;
 use actix_web::{
     App, Error, HttpRequest, HttpResponse, HttpServer, Responder, web,
 };
-use actix_web::http::StatusCode;
+use actix_web::dev::Service;
+use actix_web::http::{Method, StatusCode};
 use actix_web_actors::ws;
 use clap;
+use futures_util::FutureExt;
 use serde_json::json;
 use serde::{Deserialize, Serialize};
 
@@ -21,17 +25,51 @@ use serde::{Deserialize, Serialize};
 use crate::display::DisplayActor;
 #[cfg(target_os = "linux")]
 use crate::gpio::GpioActor;
-use crate::relay::{GetInputs, GetOutput, GetOutputDailySchedule, GetSystemTime, RegisterForStatus, RelayActor, SetOutput, SetSystemTime, SystemTime, GetOutputCustomSchedule, DailyEvent, CustomEvent, SetOutputCustomSchedule, SetOutputDailySchedule, ClearOutputDailySchedule, ClearOutputCustomSchedule};
+use crate::presence::{DeleteDevice, DeviceRequest, GetPresence, PresenceActor, PresenceConfig, PresenceReport, RegisterDevice, ReportPresence};
+use crate::relay::{GetInputs, GetOutput, GetOutputDailySchedule, GetSystemTime, RegisterForStatus, RelayActor, SetOutput, SetSystemTime, SystemTime, GetOutputCustomSchedule, DailyEvent, CustomEvent, SetOutputCustomSchedule, SetOutputDailySchedule, ClearOutputDailySchedule, ClearOutputCustomSchedule, InterlockGroup, OutputError, Pulse, PulseOutput, CancelPulse, GetPulse, GetZones};
+use crate::ajax::{AjaxActor, AjaxConfig, GetHubStatus, HubCommandError, RunHubCommand};
+use crate::alarm::{AcknowledgeAlarmEvent, AcknowledgeRequest, AlarmActor, AlarmConfig, AlarmError, AlarmEventsQuery, GetAlarmEvents, ResolveAlarmEvent, Arm, ArmRequest, Disarm, PinRequest, GetAlarmStatus, GetReadiness, RaiseAlarm, RaiseAlarmRequest, ReadinessQuery};
+use crate::auto_arm::{AutoArmActor, AutoArmConfig, AutoArmSchedule, DeleteAutoArmSchedule, GetAutoArmSchedules, GetAutoArmStatus, PostponeAutoArm, PostponeRequest, SetAutoArmSchedule};
+use crate::calendar::Calendar;
+use crate::event_store::{ChannelSummary, EventQuery, EventStoreActor, EventStoreConfig, ExportFormat, GetHistory, HistoryQuery, HistorySegment, QueryEvents};
+use crate::compiler::{CompileError, CompileQuery};
+use crate::ical::ImportQuery;
+use crate::rules::{DeleteRule, GetRuleLog, GetRules, Rule, RulesActor, SetRule};
+use crate::simulation::SimulationQuery;
+use crate::siren::{GetSirenStatus, ResetSirens, SilenceSirens, SirenActor, SirenConfig};
+use crate::scheduler::{CustomEventsError, CustomEventsRequest, DeleteCalendar, DeleteSchedule, GenerateCustomEvents, GetCalendars, GetSchedules, Schedule, SchedulerActor, SetCalendar, SetSchedule};
+use crate::walk_test::{GetWalkTest, StartWalkTest, StopWalkTest, WalkTestActor, WalkTestConfig, WalkTestError, WalkTestReport};
 use crate::web_socket::ClientWebSocket;
+use crate::users::{DeleteUser, GetUsers, PinError, SetUser, UserRequest, UsersActor, VerifyPin};
+use crate::zones::Zone;
 
+mod ajax;
+mod alarm;
+mod auto_arm;
+mod calendar;
+mod compiler;
+mod event_store;
+mod events;
+mod ical;
+mod presence;
 mod relay;
+mod rules;
+mod scheduler;
+mod simulation;
+mod siren;
+mod storage;
+mod users;
+mod walk_test;
 mod web_socket;
+mod zones;
 
 #[cfg(target_os = "linux")]
 mod gpio;
 #[cfg(target_os = "linux")]
 mod display;
 
+const MAX_AUDIT_BODY: usize = 4096;
+
 type Port = u16;
 
 struct Program {
@@ -44,6 +82,35 @@ struct ProgramConfig {
     pub relay_port: u16,
     pub inputs_number: usize,
     pub outputs_number: usize,
+    pub data_dir: String,
+    pub compile_window: usize,
+    pub interlocks: Vec<InterlockGroup>,
+    pub max_on_secs: HashMap<usize, u64>,
+    pub native_pulse: bool,
+    pub alarm: AlarmConfig,
+    pub zones: Vec<Zone>,
+    pub sirens: Vec<SirenConfig>,
+    pub walk_test: WalkTestConfig,
+    pub auto_arm: AutoArmConfig,
+    pub presence: PresenceConfig,
+    pub ajax: AjaxConfig,
+    pub event_store: EventStoreConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct FileConfig {
+    pub interlocks: Vec<InterlockGroup>,
+    pub max_on_secs: HashMap<usize, u64>,
+    pub native_pulse: bool,
+    pub alarm: AlarmConfig,
+    pub zones: Vec<Zone>,
+    pub sirens: Vec<SirenConfig>,
+    pub walk_test: WalkTestConfig,
+    pub auto_arm: AutoArmConfig,
+    pub presence: PresenceConfig,
+    pub ajax: AjaxConfig,
+    pub event_store: EventStoreConfig,
 }
 
 impl Program {
@@ -74,6 +141,21 @@ impl Program {
                 .long("outputs-number")
                 .value_name("NUMBER")
                 .about("Number of outputs, defaults to 4"))
+            .arg(clap::Arg::new("data_dir")
+                .short('d')
+                .long("data-dir")
+                .value_name("DIR")
+                .about("Directory for calendars, schedules and other persistent data, defaults to data"))
+            .arg(clap::Arg::new("compile_window")
+                .short('w')
+                .long("compile-window")
+                .value_name("DAYS")
+                .about("Days of gateway schedules compiled to the relay every midnight, defaults to 0 (disabled)"))
+            .arg(clap::Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .about("JSON configuration file with output interlocks, maximum on-times and other settings"))
             .get_matches();
 
         let host = matches.value_of("host")
@@ -111,12 +193,54 @@ impl Program {
                 process::exit(-1);
             });
 
+        let data_dir = matches.value_of("data_dir")
+            .unwrap_or("data")
+            .to_string();
+
+        let compile_window = matches.value_of("compile_window")
+            .unwrap_or("0")
+            .parse::<usize>()
+            .unwrap_or_else(|error| {
+                Program::print_error(format!("invalid compile window: {}", error));
+                clap.write_long_help(&mut io::stdout()).unwrap();
+                process::exit(-1);
+            });
+
+        let file_config = matches.value_of("config")
+            .map(|path| File::open(path)
+                .map_err(|error| error.to_string())
+                .and_then(|file| serde_json::from_reader::<_, FileConfig>(BufReader::new(file))
+                    .map_err(|error| error.to_string()))
+                .unwrap_or_else(|error| {
+                    Program::print_error(format!("invalid config file: {}", error));
+                    process::exit(-1);
+                }))
+            .unwrap_or_default();
+
+        if let Err(error) = file_config.ajax.validate(inputs_number) {
+            Program::print_error(format!("invalid config file: {}", error));
+            process::exit(-1);
+        }
+
         Program {
             config: ProgramConfig {
                 relay_host: host,
                 relay_port: port,
                 inputs_number,
                 outputs_number,
+                data_dir,
+                compile_window,
+                interlocks: file_config.interlocks,
+                max_on_secs: file_config.max_on_secs,
+                native_pulse: file_config.native_pulse,
+                alarm: file_config.alarm,
+                zones: file_config.zones,
+                sirens: file_config.sirens,
+                walk_test: file_config.walk_test,
+                auto_arm: file_config.auto_arm,
+                presence: file_config.presence,
+                ajax: file_config.ajax,
+                event_store: file_config.event_store,
             }
         }
     }
@@ -130,7 +254,9 @@ async fn ws_index(
     r: HttpRequest,
     stream: web::Payload,
 ) -> Result<HttpResponse, Error> {
-    ws::start(ClientWebSocket::new(), &r, stream)
+    let client = client_addr(&r);
+
+    ws::start(ClientWebSocket::new(client), &r, stream)
 }
 
 async fn get_config(config: web::Data<ProgramConfig>) -> HttpResponse {
@@ -171,6 +297,19 @@ async fn inputs() -> HttpResponse {
     }
 }
 
+async fn zones() -> HttpResponse {
+    let res = RelayActor::from_registry()
+        .send(GetZones).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
 async fn get_output(web::Path(number): web::Path<usize>) -> HttpResponse {
     let res = RelayActor::from_registry()
         .send(GetOutput { number }).await;
@@ -185,9 +324,53 @@ async fn get_output(web::Path(number): web::Path<usize>) -> HttpResponse {
 }
 
 async fn set_output(web::Path((number, state)): web::Path<(usize, u32)>) -> HttpResponse {
-    let res = RelayActor::from_registry().do_send(SetOutput { number, state });
+    let res = RelayActor::from_registry()
+        .send(SetOutput { number, state }).await;
 
-    HttpResponse::Ok().finish()
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_output_pulse(web::Path(number): web::Path<usize>) -> HttpResponse {
+    let res = RelayActor::from_registry()
+        .send(GetPulse { number }).await;
+
+    if let Ok(Some(res)) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn pulse_output(web::Path(number): web::Path<usize>, web::Json(pulse): web::Json<Pulse>) -> HttpResponse {
+    let res = RelayActor::from_registry()
+        .send(PulseOutput { number, pulse }).await;
+
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn cancel_output_pulse(web::Path(number): web::Path<usize>) -> HttpResponse {
+    let res = RelayActor::from_registry()
+        .send(CancelPulse { number }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
 }
 
 async fn get_output_daily_schedule(web::Path(number): web::Path<usize>) -> HttpResponse {
@@ -203,10 +386,13 @@ async fn get_output_daily_schedule(web::Path(number): web::Path<usize>) -> HttpR
     }
 }
 
-async fn set_output_daily_schedule(web::Path(number): web::Path<usize>, web::Json(event): web::Json<DailyEvent>) -> HttpResponse {
-    let res = RelayActor::from_registry().do_send(SetOutputDailySchedule { number, event });
+async fn set_output_daily_schedule(req: HttpRequest, web::Path(number): web::Path<usize>, web::Json(event): web::Json<DailyEvent>) -> HttpResponse {
+    audit_body(&req, &event);
 
-    HttpResponse::Ok().finish()
+    let res = RelayActor::from_registry()
+        .send(SetOutputDailySchedule { number, event }).await;
+
+    schedule_response(res)
 }
 
 async fn clear_output_daily_schedule(web::Path(number): web::Path<usize>) -> HttpResponse {
@@ -228,10 +414,23 @@ async fn get_output_custom_schedule(web::Path(number): web::Path<usize>) -> Http
     }
 }
 
-async fn set_output_custom_schedule(web::Path(number): web::Path<usize>, web::Json(event): web::Json<CustomEvent>) -> HttpResponse {
-    let res = RelayActor::from_registry().do_send(SetOutputCustomSchedule { number, event });
+async fn set_output_custom_schedule(req: HttpRequest, web::Path(number): web::Path<usize>, web::Json(event): web::Json<CustomEvent>) -> HttpResponse {
+    audit_body(&req, &event);
 
-    HttpResponse::Ok().finish()
+    let res = RelayActor::from_registry()
+        .send(SetOutputCustomSchedule { number, event }).await;
+
+    schedule_response(res)
+}
+
+fn schedule_response(res: Result<Result<(), OutputError>, MailboxError>) -> HttpResponse {
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
 }
 
 async fn clear_output_custom_schedule(web::Path(number): web::Path<usize>) -> HttpResponse {
@@ -240,16 +439,854 @@ async fn clear_output_custom_schedule(web::Path(number): web::Path<usize>) -> Ht
     HttpResponse::Ok().finish()
 }
 
+async fn get_calendars() -> HttpResponse {
+    let res = SchedulerActor::from_registry()
+        .send(GetCalendars).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn set_calendar(req: HttpRequest, web::Path(name): web::Path<String>, web::Json(calendar): web::Json<Calendar>) -> HttpResponse {
+    audit_body(&req, &calendar);
+
+    let res = SchedulerActor::from_registry().do_send(SetCalendar { name, calendar });
+
+    HttpResponse::Ok().finish()
+}
+
+async fn delete_calendar(web::Path(name): web::Path<String>) -> HttpResponse {
+    let res = SchedulerActor::from_registry()
+        .send(DeleteCalendar { name }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn generate_custom_events(web::Path(calendar): web::Path<String>, web::Json(request): web::Json<CustomEventsRequest>) -> HttpResponse {
+    let res = SchedulerActor::from_registry()
+        .send(GenerateCustomEvents { calendar, request }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(CustomEventsError::CalendarNotFound)) => HttpResponse::NotFound().finish(),
+        Ok(Err(err)) => HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_schedules() -> HttpResponse {
+    let res = SchedulerActor::from_registry()
+        .send(GetSchedules).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn set_schedule(req: HttpRequest, web::Path(name): web::Path<String>, web::Json(schedule): web::Json<Schedule>) -> HttpResponse {
+    audit_body(&req, &schedule);
+
+    let res = SchedulerActor::from_registry().do_send(SetSchedule { name, schedule });
+
+    HttpResponse::Ok().finish()
+}
+
+async fn delete_schedule(web::Path(name): web::Path<String>) -> HttpResponse {
+    let res = SchedulerActor::from_registry()
+        .send(DeleteSchedule { name }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn export_ical(config: web::Data<ProgramConfig>) -> HttpResponse {
+    let res = ical::export(config.outputs_number).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("text/calendar")
+            .header("Content-Disposition", "attachment; filename=\"schedules.ics\"")
+            .body(res)
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn import_ical(req: HttpRequest, config: web::Data<ProgramConfig>, web::Query(query): web::Query<ImportQuery>, body: String) -> HttpResponse {
+    audit_body(&req, &body);
+
+    let interlocked = relay::interlocked_outputs(&config.interlocks);
+    let res = ical::import(body.as_str(), query, &interlocked).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn compile_schedules(config: web::Data<ProgramConfig>, web::Query(query): web::Query<CompileQuery>) -> HttpResponse {
+    let window = query.window.unwrap_or(if config.compile_window > 0 { config.compile_window } else { 7 });
+    let interlocked = relay::interlocked_outputs(&config.interlocks);
+    let res = compiler::compile(config.outputs_number, &interlocked, window, query.dry_run).await;
+
+    match res {
+        Ok(res) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Err(CompileError::Unavailable) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+    }
+}
+
+async fn verify_compiled_schedules() -> HttpResponse {
+    let res = compiler::verify().await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn simulate(config: web::Data<ProgramConfig>, web::Query(query): web::Query<SimulationQuery>) -> HttpResponse {
+    let res = simulation::simulate(config.outputs_number, query).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::BadRequest().finish()
+    }
+}
+
+async fn get_rules() -> HttpResponse {
+    let res = RulesActor::from_registry()
+        .send(GetRules).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn set_rule(web::Path(name): web::Path<String>, web::Json(rule): web::Json<Rule>) -> HttpResponse {
+    let res = RulesActor::from_registry()
+        .send(SetRule { name, rule }).await;
+
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(err)) => HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn delete_rule(web::Path(name): web::Path<String>) -> HttpResponse {
+    let res = RulesActor::from_registry()
+        .send(DeleteRule { name }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn get_rule_log(web::Path(name): web::Path<String>) -> HttpResponse {
+    let res = RulesActor::from_registry()
+        .send(GetRuleLog { name }).await;
+
+    if let Ok(Ok(res)) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn get_alarm_status() -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(GetAlarmStatus).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn get_alarm_readiness(web::Query(query): web::Query<ReadinessQuery>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(GetReadiness { mode: query.mode }).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+fn alarm_error(err: AlarmError) -> HttpResponse {
+    match err {
+        AlarmError::Unauthorized { .. } => HttpResponse::Forbidden()
+            .content_type("application/json")
+            .body(json!(err)),
+        AlarmError::EventNotFound { .. } => HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(json!(err)),
+        AlarmError::InvalidBypass { .. } => HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(json!(err)),
+        _ => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+    }
+}
+
+async fn arm_alarm(req: HttpRequest, web::Json(request): web::Json<ArmRequest>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(Arm { mode: request.mode, pin: request.pin, bypass: request.bypass, client: client_addr(&req) }).await;
+
+    match res {
+        Ok(Ok(res)) => {
+            audit_user(&req, res.user.clone());
+
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(json!(res))
+        }
+        Ok(Err(err)) => alarm_error(err),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn disarm_alarm(req: HttpRequest, web::Json(request): web::Json<PinRequest>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(Disarm { pin: request.pin, client: client_addr(&req) }).await;
+
+    match res {
+        Ok(Ok(res)) => {
+            audit_user(&req, res.user.clone());
+
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(json!(res))
+        }
+        Ok(Err(err)) => alarm_error(err),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn raise_alarm(req: HttpRequest, web::Json(request): web::Json<RaiseAlarmRequest>) -> HttpResponse {
+    let client = format!("http:{}", client_addr(&req));
+
+    let res = AlarmActor::from_registry()
+        .send(RaiseAlarm { kind: request.kind, silent: request.silent, client, pin: request.pin }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => alarm_error(err),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_alarm_events(web::Query(query): web::Query<AlarmEventsQuery>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(GetAlarmEvents { state: query.state }).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn acknowledge_alarm_event(req: HttpRequest, web::Path(id): web::Path<u64>, web::Json(request): web::Json<AcknowledgeRequest>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(AcknowledgeAlarmEvent { id, pin: request.pin, comment: request.comment, client: client_addr(&req) }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => alarm_error(err),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn resolve_alarm_event(req: HttpRequest, web::Path(id): web::Path<u64>, web::Json(request): web::Json<PinRequest>) -> HttpResponse {
+    let res = AlarmActor::from_registry()
+        .send(ResolveAlarmEvent { id, pin: request.pin, client: client_addr(&req) }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => alarm_error(err),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+/// The address failed PIN attempts are counted against. Forwarding headers are ignored because clients can set them.
+fn client_addr(req: &HttpRequest) -> String {
+    req.peer_addr().map_or("unknown".to_string(), |addr| addr.ip().to_string())
+}
+
+/// The user whose PIN authorised a request, for the audit log.
+struct VerifiedUser(String);
+
+/// The submitted content of a schedule edit, for the audit log.
+struct AuditBody(serde_json::Value);
+
+fn audit_user(req: &HttpRequest, user: Option<String>) {
+    if let Some(user) = user {
+        req.extensions_mut().insert(VerifiedUser(user));
+    }
+}
+
+/// Large bodies such as calendar uploads are only recorded by size so they do not bloat the event store.
+fn audit_body<T: Serialize>(req: &HttpRequest, body: &T) {
+    let body = json!(body);
+    let bytes = body.to_string().len();
+    let body = if bytes > MAX_AUDIT_BODY { json!({ "truncated": true, "bytes": bytes }) } else { body };
+
+    req.extensions_mut().insert(AuditBody(body));
+}
+
+async fn verify_user(req: &HttpRequest, pin: Option<String>) -> Result<(), HttpResponse> {
+    let res = UsersActor::from_registry()
+        .send(VerifyPin { pin, required: true, admin: false, installer: false, count_failures: true, client: client_addr(req) }).await;
+
+    match res {
+        Ok(Ok(user)) => {
+            audit_user(req, user.map(|user| user.name));
+
+            Ok(())
+        }
+        Ok(Err(err)) => Err(HttpResponse::Forbidden()
+            .content_type("application/json")
+            .body(json!(err))),
+        Err(_) => Err(HttpResponse::NoContent().finish()),
+    }
+}
+
+async fn verify_admin(req: &HttpRequest) -> Result<(), HttpResponse> {
+    verify_privileged(req, false).await
+}
+
+/// Installers may run maintenance such as the walk test but do not manage users or settings.
+async fn verify_installer(req: &HttpRequest) -> Result<(), HttpResponse> {
+    verify_privileged(req, true).await
+}
+
+async fn verify_privileged(req: &HttpRequest, installer: bool) -> Result<(), HttpResponse> {
+    let pin = req.headers().get("X-Pin")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let res = UsersActor::from_registry()
+        .send(VerifyPin { pin, required: true, admin: true, installer, count_failures: true, client: client_addr(req) }).await;
+
+    match res {
+        Ok(Ok(user)) => {
+            audit_user(req, user.map(|user| user.name));
+
+            Ok(())
+        }
+        Ok(Err(err)) => Err(HttpResponse::Forbidden()
+            .content_type("application/json")
+            .body(json!(err))),
+        Err(_) => Err(HttpResponse::NoContent().finish()),
+    }
+}
+
+async fn get_users(req: HttpRequest) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let res = UsersActor::from_registry()
+        .send(GetUsers).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn set_user(req: HttpRequest, web::Path(name): web::Path<String>, web::Json(request): web::Json<UserRequest>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let res = UsersActor::from_registry()
+        .send(SetUser { name, pin: request.pin, admin: request.admin, installer: request.installer, duress: request.duress }).await;
+
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(err)) => HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn delete_user(req: HttpRequest, web::Path(name): web::Path<String>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let res = UsersActor::from_registry()
+        .send(DeleteUser { name }).await;
+
+    match res {
+        Ok(Ok(_)) => HttpResponse::Ok().finish(),
+        Ok(Err(PinError::NotFound)) => HttpResponse::NotFound().finish(),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_auto_arm_status() -> HttpResponse {
+    let res = AutoArmActor::from_registry()
+        .send(GetAutoArmStatus).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn postpone_auto_arm(req: HttpRequest, request: Option<web::Json<PostponeRequest>>) -> HttpResponse {
+    let request = request.map(|request| request.into_inner()).unwrap_or_default();
+
+    if let Err(res) = verify_user(&req, request.pin).await {
+        return res;
+    }
+
+    let res = AutoArmActor::from_registry()
+        .send(PostponeAutoArm { secs: request.secs }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_auto_arm_schedules() -> HttpResponse {
+    let res = AutoArmActor::from_registry()
+        .send(GetAutoArmSchedules).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn set_auto_arm_schedule(req: HttpRequest, web::Path(name): web::Path<String>, web::Json(schedule): web::Json<AutoArmSchedule>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    audit_body(&req, &schedule);
+
+    AutoArmActor::from_registry().do_send(SetAutoArmSchedule { name, schedule });
+
+    HttpResponse::Ok().finish()
+}
+
+async fn delete_auto_arm_schedule(req: HttpRequest, web::Path(name): web::Path<String>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let res = AutoArmActor::from_registry()
+        .send(DeleteAutoArmSchedule { name }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn get_hub_status() -> HttpResponse {
+    let res = AjaxActor::from_registry()
+        .send(GetHubStatus).await;
+
+    if let Ok(Some(res)) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn run_hub_command(req: HttpRequest, web::Path(name): web::Path<String>, web::Json(request): web::Json<PinRequest>) -> HttpResponse {
+    if let Err(res) = verify_user(&req, request.pin).await {
+        return res;
+    }
+
+    let res = AjaxActor::from_registry()
+        .send(RunHubCommand { name }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => {
+            let mut response = match err {
+                HubCommandError::UnknownCommand => HttpResponse::NotFound(),
+                HubCommandError::Busy { .. } => HttpResponse::Conflict(),
+                HubCommandError::Timeout { .. } => HttpResponse::GatewayTimeout(),
+                _ => HttpResponse::BadGateway(),
+            };
+
+            response
+                .content_type("application/json")
+                .body(json!(err))
+        }
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_presence() -> HttpResponse {
+    let res = PresenceActor::from_registry()
+        .send(GetPresence).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn report_presence(web::Json(report): web::Json<PresenceReport>) -> HttpResponse {
+    let res = PresenceActor::from_registry()
+        .send(ReportPresence { token: report.token, state: report.state }).await;
+
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(err)) => HttpResponse::Forbidden()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn register_device(req: HttpRequest, web::Path(name): web::Path<String>, request: Option<web::Json<DeviceRequest>>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let user = request.and_then(|request| request.into_inner().user);
+
+    let res = PresenceActor::from_registry()
+        .send(RegisterDevice { name, user }).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn delete_device(req: HttpRequest, web::Path(name): web::Path<String>) -> HttpResponse {
+    if let Err(res) = verify_admin(&req).await {
+        return res;
+    }
+
+    let res = PresenceActor::from_registry()
+        .send(DeleteDevice { name }).await;
+
+    if let Ok(Ok(_)) = res {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+fn walk_test_response(res: Result<Result<WalkTestReport, WalkTestError>, MailboxError>) -> HttpResponse {
+    match res {
+        Ok(Ok(res)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res)),
+        Ok(Err(WalkTestError::NotStarted)) => HttpResponse::NotFound().finish(),
+        Ok(Err(err)) => HttpResponse::Conflict()
+            .content_type("application/json")
+            .body(json!(err)),
+        Err(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+async fn get_walk_test() -> HttpResponse {
+    walk_test_response(WalkTestActor::from_registry().send(GetWalkTest).await)
+}
+
+async fn start_walk_test(req: HttpRequest) -> HttpResponse {
+    if let Err(res) = verify_installer(&req).await {
+        return res;
+    }
+
+    walk_test_response(WalkTestActor::from_registry().send(StartWalkTest).await)
+}
+
+async fn stop_walk_test(req: HttpRequest) -> HttpResponse {
+    if let Err(res) = verify_installer(&req).await {
+        return res;
+    }
+
+    walk_test_response(WalkTestActor::from_registry().send(StopWalkTest).await)
+}
+
+async fn get_events(req: HttpRequest, query: web::Query<EventQuery>) -> HttpResponse {
+    // Restricted entries such as duress disarms are only listed once an administrator PIN verifies.
+    let restricted = if req.headers().contains_key("X-Pin") {
+        match verify_admin(&req).await {
+            Ok(()) => true,
+            Err(res) => return res,
+        }
+    } else {
+        false
+    };
+
+    let res = EventStoreActor::from_registry()
+        .send(QueryEvents { query: query.into_inner(), restricted }).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+fn export<T: Serialize>(rows: &[T], format: ExportFormat, header: &str, csv_row: fn(&T) -> String) -> HttpResponse {
+    match format {
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .body(std::iter::once(header.to_string())
+                .chain(rows.iter().map(csv_row))
+                .map(|line| line + "\n")
+                .collect::<String>()),
+        ExportFormat::Ndjson => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .body(rows.iter()
+                .map(|row| json!(row).to_string() + "\n")
+                .collect::<String>()),
+    }
+}
+
+async fn get_history(query: web::Query<HistoryQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let format = query.format;
+    let res = EventStoreActor::from_registry()
+        .send(GetHistory(query)).await;
+
+    if let Ok(res) = res {
+        export(&res, format, HistorySegment::CSV_HEADER, HistorySegment::csv_row)
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn get_history_summary(query: web::Query<HistoryQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let format = query.format;
+    let res = EventStoreActor::from_registry()
+        .send(GetHistory(query)).await;
+
+    if let Ok(res) = res {
+        export(&event_store::summarize(&res), format, ChannelSummary::CSV_HEADER, ChannelSummary::csv_row)
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+fn is_schedule_edit(path: &str) -> bool {
+    path.starts_with("/schedules") || path.starts_with("/calendars") || path.starts_with("/alarm/auto_arm/schedules")
+        || path.ends_with("/daily_schedule") || path.ends_with("/custom_schedule")
+}
+
+fn path_channel(path: &str) -> Option<usize> {
+    path.strip_prefix("/output/")
+        .and_then(|rest| rest.split('/').next())
+        .and_then(|number| number.parse().ok())
+}
+
+async fn get_siren_status() -> HttpResponse {
+    let res = SirenActor::from_registry()
+        .send(GetSirenStatus).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn silence_sirens(req: HttpRequest, web::Json(request): web::Json<PinRequest>) -> HttpResponse {
+    if let Err(res) = verify_user(&req, request.pin).await {
+        return res;
+    }
+
+    let res = SirenActor::from_registry()
+        .send(SilenceSirens).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn reset_sirens(req: HttpRequest, web::Json(request): web::Json<PinRequest>) -> HttpResponse {
+    if let Err(res) = verify_user(&req, request.pin).await {
+        return res;
+    }
+
+    let res = SirenActor::from_registry()
+        .send(ResetSirens).await;
+
+    if let Ok(res) = res {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(res))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let program = Program::new();
     let config = program.config;
     let config_clone = config.clone();
     let relay = Supervisor::start(move |_| RelayActor::new(config_clone.relay_host.as_str(), config_clone.relay_port,
-                                                           config_clone.inputs_number, config_clone.outputs_number));
+                                                           config_clone.inputs_number, config_clone.outputs_number,
+                                                           config_clone.interlocks.clone(),
+                                                           config_clone.max_on_secs.clone(),
+                                                           config_clone.native_pulse,
+                                                           config_clone.zones.clone()));
 
     SystemRegistry::set(relay.clone());
 
+    let event_store = EventStoreActor::new(config.data_dir.as_str(), config.event_store.clone()).start();
+
+    SystemRegistry::set(event_store.clone());
+
+    let scheduler = SchedulerActor::new(config.data_dir.as_str(), config.outputs_number,
+                                        relay::interlocked_outputs(&config.interlocks),
+                                        config.compile_window).start();
+
+    SystemRegistry::set(scheduler.clone());
+
+    let rules = RulesActor::new(config.data_dir.as_str(), config.inputs_number, config.outputs_number).start();
+
+    SystemRegistry::set(rules.clone());
+
+    let users = UsersActor::load(config.data_dir.as_str()).unwrap_or_else(|error| {
+        Program::print_error(error);
+        process::exit(-1);
+    });
+    let users = UsersActor::new(config.data_dir.as_str(), users, config.alarm.max_pin_attempts,
+                                config.alarm.pin_lockout_secs).start();
+
+    SystemRegistry::set(users.clone());
+
+    let sirens = SirenActor::new(config.sirens.clone()).start();
+
+    SystemRegistry::set(sirens.clone());
+
+    let alarm = AlarmActor::new(config.data_dir.as_str(), config.alarm.clone(), config.zones.clone()).start();
+
+    SystemRegistry::set(alarm.clone());
+
+    let auto_arm = AutoArmActor::new(config.data_dir.as_str(), config.auto_arm.clone()).start();
+
+    SystemRegistry::set(auto_arm.clone());
+
+    let ajax = AjaxActor::new(config.ajax.clone()).start();
+
+    SystemRegistry::set(ajax.clone());
+
+    let presence = PresenceActor::new(config.data_dir.as_str(), config.presence.clone()).start();
+
+    SystemRegistry::set(presence.clone());
+
+    let walk_test = WalkTestActor::new(config.walk_test.clone(), config.zones.clone()).start();
+
+    SystemRegistry::set(walk_test.clone());
+
     #[cfg(target_os = "linux")] {
         let gpio = GpioActor::new().start();
         let display = DisplayActor::new().start();
@@ -258,12 +1295,44 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .data(config.clone())
+            .wrap_fn(|req, srv| {
+                let method = req.method().clone();
+                let path = req.path().to_string();
+                let actor = req.peer_addr().map(|addr| addr.ip().to_string());
+
+                srv.call(req).map(move |res| {
+                    if method != Method::GET {
+                        if let Ok(ref res) = res {
+                            let extensions = res.request().extensions();
+                            let event_type = if is_schedule_edit(&path) { "schedule_edit" } else { "api_command" };
+                            let mut data = json!({
+                                "method": method.as_str(),
+                                "path": path,
+                                "status": res.status().as_u16(),
+                                "user": extensions.get::<VerifiedUser>().map(|user| user.0.clone()),
+                            });
+
+                            if let Some(body) = extensions.get::<AuditBody>() {
+                                data["body"] = body.0.clone();
+                            }
+
+                            event_store::record(event_type, path_channel(&path), actor, data);
+                        }
+                    }
+
+                    res
+                })
+            })
             .service(web::resource("/ws/").route(web::get().to(ws_index)))
             .route("/config", web::get().to(get_config))
             .route("/system_time", web::get().to(get_system_time))
             .route("/system_time", web::put().to(set_system_time))
             .route("/inputs", web::get().to(inputs))
+            .route("/zones", web::get().to(zones))
             .route("/output/{number}", web::get().to(get_output))
+            .route("/output/{number}/pulse", web::get().to(get_output_pulse))
+            .route("/output/{number}/pulse", web::post().to(pulse_output))
+            .route("/output/{number}/pulse", web::delete().to(cancel_output_pulse))
             .route("/output/{number}/{state}", web::post().to(set_output))
             .route("/output/{number}/daily_schedule", web::get().to(get_output_daily_schedule))
             .route("/output/{number}/daily_schedule", web::put().to(set_output_daily_schedule))
@@ -271,6 +1340,53 @@ async fn main() -> std::io::Result<()> {
             .route("/output/{number}/custom_schedule", web::get().to(get_output_custom_schedule))
             .route("/output/{number}/custom_schedule", web::put().to(set_output_custom_schedule))
             .route("/output/{number}/custom_schedule", web::delete().to(clear_output_custom_schedule))
+            .route("/calendars", web::get().to(get_calendars))
+            .route("/calendars/{name}", web::put().to(set_calendar))
+            .route("/calendars/{name}", web::delete().to(delete_calendar))
+            .route("/calendars/{name}/custom_events", web::post().to(generate_custom_events))
+            .route("/schedules", web::get().to(get_schedules))
+            .route("/schedules.ics", web::get().to(export_ical))
+            .route("/schedules.ics", web::post().to(import_ical))
+            .route("/schedules/compile", web::post().to(compile_schedules))
+            .route("/schedules/compile/verify", web::get().to(verify_compiled_schedules))
+            .route("/schedules/{name}", web::put().to(set_schedule))
+            .route("/schedules/{name}", web::delete().to(delete_schedule))
+            .route("/simulation", web::get().to(simulate))
+            .route("/alarm", web::get().to(get_alarm_status))
+            .route("/alarm/readiness", web::get().to(get_alarm_readiness))
+            .route("/alarm/arm", web::post().to(arm_alarm))
+            .route("/alarm/disarm", web::post().to(disarm_alarm))
+            .route("/alarm/raise", web::post().to(raise_alarm))
+            .route("/alarm/events", web::get().to(get_alarm_events))
+            .route("/alarm/events/{id}/acknowledge", web::post().to(acknowledge_alarm_event))
+            .route("/alarm/events/{id}/resolve", web::post().to(resolve_alarm_event))
+            .route("/alarm/auto_arm", web::get().to(get_auto_arm_status))
+            .route("/alarm/auto_arm/postpone", web::post().to(postpone_auto_arm))
+            .route("/alarm/auto_arm/schedules", web::get().to(get_auto_arm_schedules))
+            .route("/alarm/auto_arm/schedules/{name}", web::put().to(set_auto_arm_schedule))
+            .route("/alarm/auto_arm/schedules/{name}", web::delete().to(delete_auto_arm_schedule))
+            .route("/hub", web::get().to(get_hub_status))
+            .route("/hub/{command}", web::post().to(run_hub_command))
+            .route("/presence", web::get().to(get_presence))
+            .route("/presence", web::post().to(report_presence))
+            .route("/presence/devices/{name}", web::put().to(register_device))
+            .route("/presence/devices/{name}", web::delete().to(delete_device))
+            .route("/walk_test", web::get().to(get_walk_test))
+            .route("/walk_test/start", web::post().to(start_walk_test))
+            .route("/walk_test/stop", web::post().to(stop_walk_test))
+            .route("/users", web::get().to(get_users))
+            .route("/users/{name}", web::put().to(set_user))
+            .route("/users/{name}", web::delete().to(delete_user))
+            .route("/sirens", web::get().to(get_siren_status))
+            .route("/sirens/silence", web::post().to(silence_sirens))
+            .route("/sirens/reset", web::post().to(reset_sirens))
+            .route("/rules", web::get().to(get_rules))
+            .route("/rules/{name}", web::put().to(set_rule))
+            .route("/rules/{name}", web::delete().to(delete_rule))
+            .route("/rules/{name}/log", web::get().to(get_rule_log))
+            .route("/events", web::get().to(get_events))
+            .route("/history", web::get().to(get_history))
+            .route("/history/summary", web::get().to(get_history_summary))
             .service(fs::Files::new("/", "static/").index_file("index.html"))
     })
         .bind("0.0.0.0:8080")?
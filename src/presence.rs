@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Local, NaiveDateTime};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::alarm::{AlarmActor, ArmMode, SystemArm, SystemDisarm};
+use crate::events::{self, EventKind};
+use crate::storage;
+
+const DEVICES_FILE: &str = "presence.json";
+const TOKEN_LENGTH: usize = 24;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    Home,
+    Away,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AwayPolicy {
+    ArmAway,
+    ArmHome,
+    Notify,
+    Ignore,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnPolicy {
+    Disarm,
+    Notify,
+    Ignore,
+}
+
+fn default_away_policy() -> AwayPolicy {
+    AwayPolicy::Notify
+}
+
+fn default_return_policy() -> ReturnPolicy {
+    ReturnPolicy::Notify
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PresenceConfig {
+    #[serde(default = "default_away_policy")]
+    pub everyone_away: AwayPolicy,
+    #[serde(default = "default_return_policy")]
+    pub someone_home: ReturnPolicy,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            everyone_away: default_away_policy(),
+            someone_home: default_return_policy(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Device {
+    pub token_hash: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    pub state: PresenceState,
+    pub changed_at: NaiveDateTime,
+}
+
+pub type Devices = HashMap<String, Device>;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DevicePresence {
+    pub device: String,
+    pub user: Option<String>,
+    pub state: PresenceState,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct PresenceReport {
+    pub token: String,
+    pub state: PresenceState,
+}
+
+#[derive(Default, Deserialize)]
+pub struct DeviceRequest {
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceToken {
+    pub device: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum PresenceError {
+    UnknownToken,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<DevicePresence>")]
+pub struct GetPresence;
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<DevicePresence>, PresenceError>")]
+pub struct ReportPresence {
+    pub token: String,
+    pub state: PresenceState,
+}
+
+#[derive(Message)]
+#[rtype(result = "DeviceToken")]
+pub struct RegisterDevice {
+    pub name: String,
+    pub user: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct DeleteDevice {
+    pub name: String,
+}
+
+pub struct PresenceActor {
+    data_dir: String,
+    config: PresenceConfig,
+    devices: Devices,
+}
+
+impl Default for PresenceActor {
+    fn default() -> Self {
+        PresenceActor {
+            data_dir: "data".to_string(),
+            config: PresenceConfig::default(),
+            devices: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for PresenceActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _: &mut Self::Context) {
+        self.devices = storage::load(&self.devices_path());
+    }
+}
+
+impl Supervised for PresenceActor {}
+
+impl SystemService for PresenceActor {}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl PresenceActor {
+    pub fn new(data_dir: &str, config: PresenceConfig) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            config,
+            ..PresenceActor::default()
+        }
+    }
+
+    fn devices_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, DEVICES_FILE)
+    }
+
+    fn presence(&self) -> Vec<DevicePresence> {
+        let mut presence = self.devices.iter()
+            .map(|(name, device)| DevicePresence {
+                device: name.clone(),
+                user: device.user.clone(),
+                state: device.state,
+                changed_at: device.changed_at,
+            })
+            .collect::<Vec<DevicePresence>>();
+
+        presence.sort_by(|a, b| a.device.cmp(&b.device));
+        presence
+    }
+
+    fn everyone_away(&self) -> bool {
+        !self.devices.is_empty() && self.devices.values().all(|device| device.state == PresenceState::Away)
+    }
+
+    fn apply_policy(&self, device: &str, was_away: bool) {
+        let source = format!("presence:{}", device);
+
+        if !was_away && self.everyone_away() {
+            println!("Everyone is away, policy {:?}", self.config.everyone_away);
+
+            match self.config.everyone_away {
+                AwayPolicy::ArmAway => PresenceActor::arm(ArmMode::Away, source),
+                AwayPolicy::ArmHome => PresenceActor::arm(ArmMode::Home, source),
+                AwayPolicy::Notify => events::publish(EventKind::PresenceNotification {
+                    message: "Everyone has left".to_string(),
+                }),
+                AwayPolicy::Ignore => (),
+            }
+        } else if was_away && !self.everyone_away() {
+            println!("{} returned home, policy {:?}", device, self.config.someone_home);
+
+            match self.config.someone_home {
+                ReturnPolicy::Disarm => PresenceActor::disarm(source),
+                ReturnPolicy::Notify => events::publish(EventKind::PresenceNotification {
+                    message: format!("{} returned home", device),
+                }),
+                ReturnPolicy::Ignore => (),
+            }
+        }
+    }
+
+    fn arm(mode: ArmMode, source: String) {
+        actix::spawn(async move {
+            if let Ok(Err(error)) = AlarmActor::from_registry().send(SystemArm { mode, source }).await {
+                println!("Presence arming failed: {:?}", error);
+
+                events::publish(EventKind::PresenceNotification {
+                    message: format!("Automatic arming failed: {:?}", error),
+                });
+            }
+        });
+    }
+
+    fn disarm(source: String) {
+        actix::spawn(async move {
+            if let Ok(Err(error)) = AlarmActor::from_registry().send(SystemDisarm { source }).await {
+                println!("Presence disarming skipped: {:?}", error);
+            }
+        });
+    }
+}
+
+impl Handler<GetPresence> for PresenceActor {
+    type Result = MessageResult<GetPresence>;
+
+    fn handle(&mut self, _: GetPresence, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.presence())
+    }
+}
+
+impl Handler<ReportPresence> for PresenceActor {
+    type Result = Result<Vec<DevicePresence>, PresenceError>;
+
+    fn handle(&mut self, message: ReportPresence, _: &mut Context<Self>) -> Self::Result {
+        let token_hash = hash_token(&message.token);
+        let was_away = self.everyone_away();
+
+        let (name, device) = self.devices.iter_mut()
+            .find(|(_, device)| device.token_hash == token_hash)
+            .ok_or(PresenceError::UnknownToken)?;
+
+        if device.state == message.state {
+            return Ok(self.presence());
+        }
+
+        println!("Device {} is {:?}", name, message.state);
+
+        device.state = message.state;
+        device.changed_at = Local::now().naive_local();
+
+        let name = name.clone();
+
+        storage::save(&self.devices_path(), &self.devices);
+        events::publish(EventKind::PresenceChanged { device: name.clone(), state: message.state });
+
+        self.apply_policy(&name, was_away);
+
+        Ok(self.presence())
+    }
+}
+
+impl Handler<RegisterDevice> for PresenceActor {
+    type Result = MessageResult<RegisterDevice>;
+
+    fn handle(&mut self, message: RegisterDevice, _: &mut Context<Self>) -> Self::Result {
+        let token = hex::encode(thread_rng().gen::<[u8; TOKEN_LENGTH]>());
+
+        self.devices.insert(message.name.clone(), Device {
+            token_hash: hash_token(&token),
+            user: message.user,
+            state: PresenceState::Home,
+            changed_at: Local::now().naive_local(),
+        });
+
+        storage::save(&self.devices_path(), &self.devices);
+
+        MessageResult(DeviceToken { device: message.name, token })
+    }
+}
+
+impl Handler<DeleteDevice> for PresenceActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: DeleteDevice, _: &mut Context<Self>) -> Self::Result {
+        let was_away = self.everyone_away();
+
+        self.devices.remove(&message.name).ok_or(())?;
+        storage::save(&self.devices_path(), &self.devices);
+
+        // Removing the last device at home leaves everyone away, but removing a device never counts as a return.
+        if !was_away {
+            self.apply_policy(&message.name, false);
+        }
+
+        Ok(())
+    }
+}
@@ -22,6 +22,9 @@ use tokio::sync::oneshot::{channel, Receiver, Sender};
 use tokio::time::{self, Duration, timeout};
 use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
 
+use crate::events::{self, EventKind};
+use crate::zones::{Zone, ZoneStatus, zone_statuses};
+
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
 const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
 const POLL_STATUSES_INTERVAL: Duration = Duration::from_secs(1);
@@ -29,6 +32,8 @@ const OCCH_ALL_PATTERN: &str = "+OCCH_ALL";
 const STACH_PATTERN: &str = "+STACH";
 const TIME_PATTERN: &str = "+TIME";
 const TIMESW_PATTERN: &str = "+TIMESW";
+const MAX_PULSE_MS: u64 = 3_600_000;
+const MAX_PULSE_REPEAT: u32 = 1000;
 
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -37,13 +42,13 @@ pub struct SystemTime {
     pub day_of_week: u8,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
 pub struct DailyEvent {
     pub time: String,
     pub state: u32,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
 pub struct CustomEvent {
     pub date_time: String,
     pub state: u32,
@@ -63,19 +68,105 @@ pub struct SetSystemTime {
 #[rtype(result = "Vec<u32>")]
 pub struct GetInputs;
 
+#[derive(Message)]
+#[rtype(result = "Vec<ZoneStatus>")]
+pub struct GetZones;
+
 #[derive(Message)]
 #[rtype(result = "Result<u32, ()>")]
 pub struct GetOutput {
     pub number: usize,
 }
 
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterlockMode {
+    Reject,
+    Sequence,
+}
+
+impl Default for InterlockMode {
+    fn default() -> Self {
+        InterlockMode::Reject
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct InterlockGroup {
+    pub outputs: Vec<usize>,
+    #[serde(default)]
+    pub mode: InterlockMode,
+    #[serde(default)]
+    pub dead_time_ms: u64,
+}
+
+pub fn interlocked_outputs(groups: &[InterlockGroup]) -> Vec<usize> {
+    let mut outputs = groups.iter()
+        .flat_map(|group| group.outputs.iter().cloned())
+        .collect::<Vec<usize>>();
+
+    outputs.sort_unstable();
+    outputs.dedup();
+    outputs
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum OutputError {
+    InvalidOutput { number: usize },
+    Interlocked { number: usize, active: Vec<usize> },
+    InvalidPulse { max_ms: u64, max_repeat: u32 },
+    ZeroPulseDuration,
+    ScheduleInterlocked { number: usize },
+}
+
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), OutputError>")]
 pub struct SetOutput {
     pub number: usize,
     pub state: u32,
 }
 
+fn default_repeat() -> u32 {
+    1
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Pulse {
+    pub duration_ms: u64,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    #[serde(default)]
+    pub interval_ms: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PulseStatus {
+    pub output: usize,
+    pub native: bool,
+    pub repeats_left: u32,
+    pub remaining_ms: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), OutputError>")]
+pub struct PulseOutput {
+    pub number: usize,
+    pub pulse: Pulse,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct CancelPulse {
+    pub number: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<PulseStatus>")]
+pub struct GetPulse {
+    pub number: usize,
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<Vec<DailyEvent>, ()>")]
 pub struct GetOutputDailySchedule {
@@ -83,7 +174,7 @@ pub struct GetOutputDailySchedule {
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), OutputError>")]
 pub struct SetOutputDailySchedule {
     pub number: usize,
     pub event: DailyEvent,
@@ -102,7 +193,7 @@ pub struct GetOutputCustomSchedule {
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), OutputError>")]
 pub struct SetOutputCustomSchedule {
     pub number: usize,
     pub event: CustomEvent,
@@ -122,6 +213,8 @@ pub struct RelayStatus {
     pub outputs: Option<Vec<u32>>,
     pub connected: bool,
     pub time: Option<SystemTime>,
+    pub pulses: Option<Vec<PulseStatus>>,
+    pub zones: Option<Vec<ZoneStatus>>,
 }
 
 #[derive(Message)]
@@ -132,6 +225,14 @@ pub struct RegisterForStatus(pub Recipient<RelayStatus>);
 #[rtype(result = "()")]
 pub struct UnregisterForStatus(pub usize);
 
+struct ActivePulse {
+    pulse: Pulse,
+    native: bool,
+    repeats_left: u32,
+    finishes_at: Instant,
+    handle: Option<SpawnHandle>,
+}
+
 type Framed = FramedWrite<
     String,
     WriteHalf<TcpStream>,
@@ -143,8 +244,19 @@ pub struct RelayActor {
     pub port: u16,
     pub inputs_number: usize,
     pub outputs_number: usize,
+    pub interlocks: Vec<InterlockGroup>,
+    pub max_on_secs: HashMap<usize, u64>,
+    pub native_pulse: bool,
+    pub zones: Vec<Zone>,
     inputs: Vec<u32>,
+    /// Set once the relay has reported its inputs since connecting; until then `inputs` is a placeholder.
+    inputs_reported: bool,
     inputs_mask: u32,
+    outputs: Vec<u32>,
+    outputs_switched_on: Vec<Option<Instant>>,
+    outputs_switched_off: Vec<Option<Instant>>,
+    pulses: HashMap<usize, ActivePulse>,
+    pending_on: HashMap<usize, SpawnHandle>,
     framed: Option<Framed>,
     hb: Instant,
     rng: ThreadRng,
@@ -159,8 +271,18 @@ impl Default for RelayActor {
             port: 12345,
             inputs_number: 4,
             outputs_number: 4,
+            interlocks: Vec::new(),
+            max_on_secs: HashMap::new(),
+            native_pulse: false,
+            zones: Vec::new(),
             inputs: vec![0u32; 4],
+            inputs_reported: false,
             inputs_mask: 0xff,
+            outputs: vec![0u32; 4],
+            outputs_switched_on: vec![None; 4],
+            outputs_switched_off: vec![None; 4],
+            pulses: HashMap::new(),
+            pending_on: HashMap::new(),
             framed: None,
             hb: Instant::now(),
             rng: thread_rng(),
@@ -177,6 +299,7 @@ impl Actor for RelayActor {
         println!("RelayActor started!");
 
         self.oneshots = HashMap::new();
+        self.inputs_reported = false;
 
         Resolver::from_registry()
             .send(Connect::host_and_port(self.host.as_str(), self.port))
@@ -194,10 +317,12 @@ impl Actor for RelayActor {
                         act.framed = Some(line_writer);
 
                         act.send_status(RelayStatus {
-                            inputs: Some(act.inputs.clone()),
+                            inputs: None,
                             outputs: None,
                             connected: true,
                             time: None,
+                            pulses: None,
+                            zones: None,
                         });
                     }
                     Err(err) => {
@@ -245,13 +370,23 @@ impl StreamHandler<Result<String, LinesCodecError>> for RelayActor {
 }
 
 impl RelayActor {
-    pub fn new(host: &str, port: u16, inputs_number: usize, outputs_number: usize) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(host: &str, port: u16, inputs_number: usize, outputs_number: usize,
+               interlocks: Vec<InterlockGroup>, max_on_secs: HashMap<usize, u64>, native_pulse: bool,
+               zones: Vec<Zone>) -> Self {
         Self {
             host: String::from(host),
             port,
             inputs_number,
             outputs_number,
+            interlocks,
+            max_on_secs,
+            native_pulse,
+            zones,
             inputs: vec![0u32; inputs_number],
+            outputs: vec![0u32; outputs_number],
+            outputs_switched_on: vec![None; outputs_number],
+            outputs_switched_off: vec![None; outputs_number],
             ..RelayActor::default()
         }
     }
@@ -262,11 +397,14 @@ impl RelayActor {
                 ctx.run_later(Duration::from_secs(5), |act, ctx| {
                     println!("Relay heartbeat failed, disconnecting!");
 
+                    act.inputs_reported = false;
                     act.send_status(RelayStatus {
                         inputs: None,
                         outputs: None,
                         connected: false,
                         time: None,
+                        pulses: None,
+                        zones: None,
                     });
 
                     ctx.stop();
@@ -284,6 +422,8 @@ impl RelayActor {
                         outputs: None,
                         connected: true,
                         time: Some(time),
+                        pulses: None,
+                        zones: None,
                     });
                 }
 
@@ -297,11 +437,19 @@ impl RelayActor {
         ctx.run_interval(POLL_STATUSES_INTERVAL, |act, ctx| {
             act.get_outputs(ctx).then(|outputs, act, ctx| {
                 if let Ok(outputs) = outputs {
+                    for (index, &state) in outputs.iter().enumerate() {
+                        act.update_output(index + 1, state, true);
+                    }
+
+                    act.enforce_max_on_time();
+
                     act.send_status(RelayStatus {
                         inputs: None,
                         outputs: Some(outputs),
                         connected: true,
                         time: None,
+                        pulses: Some(act.pulse_statuses()),
+                        zones: None,
                     });
                 }
 
@@ -464,15 +612,18 @@ impl RelayActor {
             states_mask += input;
         }
 
-        if states_mask != self.inputs_mask {
+        if states_mask != self.inputs_mask || !self.inputs_reported {
             self.inputs_mask = states_mask;
+            self.inputs_reported = true;
             self.inputs = states.clone();
 
             self.send_status(RelayStatus {
+                zones: Some(zone_statuses(&self.zones, &states)),
                 inputs: Some(states),
                 outputs: None,
                 connected: true,
                 time: None,
+                pulses: None,
             });
         }
     }
@@ -494,8 +645,310 @@ impl RelayActor {
 
         let key = format!("{}{}", STACH_PATTERN, output_no);
 
+        self.update_output(output_no, output_state, true);
         self.fire_oneshot(key, output_state);
     }
+
+    /// `confirmed` is set for states reported by the relay rather than assumed after a write.
+    fn update_output(&mut self, number: usize, state: u32, confirmed: bool) {
+        if number == 0 || number > self.outputs.len() {
+            return;
+        }
+
+        let index = number - 1;
+
+        if state == 0 {
+            if self.outputs[index] != 0 {
+                self.outputs_switched_off[index] = Some(Instant::now());
+            }
+
+            // Only the relay can tell the output is really off; until then a max on-time cutoff keeps retrying.
+            if confirmed {
+                self.outputs_switched_on[index] = None;
+            }
+        } else if self.outputs_switched_on[index].is_none() || (!confirmed && self.outputs[index] == 0) {
+            self.outputs_switched_on[index] = Some(Instant::now());
+        }
+
+        self.outputs[index] = state;
+    }
+
+    fn enforce_max_on_time(&mut self) {
+        let expired = self.max_on_secs.iter()
+            .filter(|(&number, &max_on_secs)| self.outputs_switched_on.get(number.wrapping_sub(1))
+                .and_then(|switched_on| *switched_on)
+                .map_or(false, |switched_on| switched_on.elapsed() >= Duration::from_secs(max_on_secs)))
+            .map(|(&number, &max_on_secs)| (number, max_on_secs))
+            .collect::<Vec<(usize, u64)>>();
+
+        for (number, max_on_secs) in expired {
+            let index = number - 1;
+            let retry = match (self.outputs_switched_on[index], self.outputs_switched_off[index]) {
+                (Some(switched_on), Some(switched_off)) => switched_off >= switched_on,
+                _ => false,
+            };
+
+            if retry {
+                println!("Output {} still on after maximum on-time cutoff, retrying", number);
+            } else {
+                println!("Output {} exceeded maximum on-time of {} s, switching off", number, max_on_secs);
+
+                events::publish(EventKind::OutputCutoff { output: number, max_on_secs });
+            }
+
+            self.write_output(number, 0);
+        }
+    }
+
+    fn write_output(&mut self, number: usize, state: u32) {
+        if let Some(ref mut line_writer) = self.framed {
+            line_writer.write(format!("AT{}{}={}", STACH_PATTERN, number, state));
+        }
+
+        self.update_output(number, state, false);
+    }
+
+    /// Returns how long a sequenced output waits before it is actually switched on.
+    fn set_output(&mut self, number: usize, state: u32, ctx: &mut <Self as Actor>::Context) -> Result<Duration, OutputError> {
+        if number == 0 || number > self.outputs_number {
+            return Err(OutputError::InvalidOutput { number });
+        }
+
+        // Any later command replaces a sequenced switch-on that is still waiting out the dead time.
+        self.cancel_pending_on(number, ctx);
+
+        if state == 0 {
+            self.write_output(number, state);
+
+            return Ok(Duration::from_millis(0));
+        }
+
+        let mut delay = Duration::from_millis(0);
+
+        for group in self.interlocks.iter().filter(|group| group.outputs.contains(&number)) {
+            let others = group.outputs.iter()
+                .filter(|&&other| other != number && other >= 1 && other <= self.outputs_number)
+                .cloned()
+                .collect::<Vec<usize>>();
+            let active = others.iter()
+                .filter(|&&other| self.outputs[other - 1] != 0)
+                .cloned()
+                .collect::<Vec<usize>>();
+            let dead_time = Duration::from_millis(group.dead_time_ms);
+
+            if !active.is_empty() {
+                match group.mode {
+                    InterlockMode::Reject => {
+                        println!("Output {} interlocked by {:?}", number, active);
+
+                        return Err(OutputError::Interlocked { number, active });
+                    }
+                    InterlockMode::Sequence => delay = delay.max(dead_time),
+                }
+            }
+
+            for other in others {
+                if let Some(switched_off) = self.outputs_switched_off[other - 1] {
+                    delay = delay.max(dead_time.checked_sub(switched_off.elapsed()).unwrap_or_default());
+                }
+            }
+        }
+
+        if delay == Duration::from_millis(0) {
+            self.write_output(number, state);
+
+            return Ok(delay);
+        }
+
+        for other in self.interlocked_with(number) {
+            if self.outputs[other - 1] != 0 {
+                self.write_output(other, 0);
+            }
+        }
+
+        let handle = ctx.run_later(delay, move |act, ctx| {
+            act.pending_on.remove(&number);
+
+            if let Err(err) = act.set_output(number, state, ctx) {
+                println!("Sequenced output {} rejected: {:?}", number, err);
+            }
+        });
+
+        self.pending_on.insert(number, handle);
+
+        Ok(delay)
+    }
+
+    fn cancel_pending_on(&mut self, number: usize, ctx: &mut <Self as Actor>::Context) {
+        if let Some(handle) = self.pending_on.remove(&number) {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    /// The relay runs native schedules by itself, which would switch interlocked outputs without the interlock.
+    fn check_native_schedule(&self, number: usize) -> Result<(), OutputError> {
+        if number == 0 || number > self.outputs_number {
+            return Err(OutputError::InvalidOutput { number });
+        }
+
+        if !self.interlocked_with(number).is_empty() {
+            return Err(OutputError::ScheduleInterlocked { number });
+        }
+
+        Ok(())
+    }
+
+    fn start_pulse(&mut self, number: usize, pulse: Pulse, ctx: &mut <Self as Actor>::Context) -> Result<(), OutputError> {
+        if number == 0 || number > self.outputs_number {
+            return Err(OutputError::InvalidOutput { number });
+        }
+
+        // A zero duration would latch the output on in the relay's native pulse command.
+        if pulse.duration_ms == 0 {
+            return Err(OutputError::ZeroPulseDuration);
+        }
+
+        if pulse.duration_ms > MAX_PULSE_MS || pulse.interval_ms > MAX_PULSE_MS || pulse.repeat > MAX_PULSE_REPEAT {
+            return Err(OutputError::InvalidPulse { max_ms: MAX_PULSE_MS, max_repeat: MAX_PULSE_REPEAT });
+        }
+
+        self.cancel_pulse(number, ctx);
+
+        let repeat = pulse.repeat.max(1);
+        let total_ms = pulse.duration_ms.saturating_mul(repeat as u64)
+            .saturating_add(pulse.interval_ms.saturating_mul((repeat - 1) as u64));
+        let native = self.native_pulse && repeat == 1 && pulse.duration_ms % 1000 == 0
+            && self.interlocked_with(number).is_empty();
+
+        self.pulses.insert(number, ActivePulse {
+            pulse,
+            native,
+            repeats_left: repeat,
+            finishes_at: Instant::now() + Duration::from_millis(total_ms),
+            handle: None,
+        });
+
+        if native {
+            let active = &self.pulses[&number];
+            let duration = Duration::from_millis(active.pulse.duration_ms);
+
+            if let Some(ref mut line_writer) = self.framed {
+                line_writer.write(format!("AT{}{}=1,{}", STACH_PATTERN, number, duration.as_secs()));
+            }
+
+            self.update_output(number, 1, false);
+
+            let handle = ctx.run_later(duration, move |act, _| {
+                act.update_output(number, 0, false);
+                act.pulses.remove(&number);
+            });
+
+            self.pulses.get_mut(&number).unwrap().handle = Some(handle);
+
+            return Ok(());
+        }
+
+        self.pulse_cycle(number, ctx)
+    }
+
+    fn pulse_cycle(&mut self, number: usize, ctx: &mut <Self as Actor>::Context) -> Result<(), OutputError> {
+        let duration = match self.pulses.get(&number) {
+            Some(active) => Duration::from_millis(active.pulse.duration_ms),
+            None => return Ok(()),
+        };
+
+        // A sequenced output may only come on after the dead time, so the pulse length counts from then.
+        let delay = match self.set_output(number, 1, ctx) {
+            Ok(delay) => delay,
+            Err(err) => {
+                self.pulses.remove(&number);
+
+                return Err(err);
+            }
+        };
+
+        let handle = ctx.run_later(delay + duration, move |act, ctx| {
+            act.write_output(number, 0);
+
+            let next = match act.pulses.get_mut(&number) {
+                Some(active) if active.repeats_left > 1 => {
+                    active.repeats_left -= 1;
+                    Some(Duration::from_millis(active.pulse.interval_ms))
+                }
+                _ => None,
+            };
+
+            match next {
+                Some(interval) => {
+                    let handle = ctx.run_later(interval, move |act, ctx| {
+                        if let Err(err) = act.pulse_cycle(number, ctx) {
+                            println!("Pulse on output {} stopped: {:?}", number, err);
+                        }
+                    });
+
+                    if let Some(active) = act.pulses.get_mut(&number) {
+                        active.handle = Some(handle);
+                    }
+                }
+                None => {
+                    act.pulses.remove(&number);
+                }
+            }
+        });
+
+        if let Some(active) = self.pulses.get_mut(&number) {
+            active.handle = Some(handle);
+        }
+
+        Ok(())
+    }
+
+    fn cancel_pulse(&mut self, number: usize, ctx: &mut <Self as Actor>::Context) -> bool {
+        self.cancel_pending_on(number, ctx);
+
+        match self.pulses.remove(&number) {
+            Some(active) => {
+                if let Some(handle) = active.handle {
+                    ctx.cancel_future(handle);
+                }
+
+                self.write_output(number, 0);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn pulse_status(&self, number: usize) -> Option<PulseStatus> {
+        self.pulses.get(&number).map(|active| PulseStatus {
+            output: number,
+            native: active.native,
+            repeats_left: active.repeats_left,
+            remaining_ms: active.finishes_at.saturating_duration_since(Instant::now()).as_millis() as u64,
+        })
+    }
+
+    fn pulse_statuses(&self) -> Vec<PulseStatus> {
+        let mut statuses = self.pulses.keys()
+            .filter_map(|&number| self.pulse_status(number))
+            .collect::<Vec<PulseStatus>>();
+
+        statuses.sort_by_key(|status| status.output);
+        statuses
+    }
+
+    fn interlocked_with(&self, number: usize) -> Vec<usize> {
+        let mut outputs = self.interlocks.iter()
+            .filter(|group| group.outputs.contains(&number))
+            .flat_map(|group| group.outputs.iter().cloned())
+            .filter(|&other| other != number && other >= 1 && other <= self.outputs_number)
+            .collect::<Vec<usize>>();
+
+        outputs.sort();
+        outputs.dedup();
+        outputs
+    }
 }
 
 impl WriteHandler<LinesCodecError> for RelayActor {}
@@ -546,6 +999,14 @@ impl Handler<GetInputs> for RelayActor {
     }
 }
 
+impl Handler<GetZones> for RelayActor {
+    type Result = MessageResult<GetZones>;
+
+    fn handle(&mut self, _: GetZones, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(zone_statuses(&self.zones, &self.inputs))
+    }
+}
+
 impl Handler<GetOutput> for RelayActor {
     type Result = ResponseActFuture<Self, Result<u32, ()>>;
 
@@ -575,13 +1036,17 @@ impl Handler<GetOutputDailySchedule> for RelayActor {
 }
 
 impl Handler<SetOutputDailySchedule> for RelayActor {
-    type Result = ();
+    type Result = Result<(), OutputError>;
 
     fn handle(&mut self, message: SetOutputDailySchedule, _: &mut Context<Self>) -> Self::Result {
+        self.check_native_schedule(message.number)?;
+
         if let Some(ref mut line_writer) = self.framed {
             line_writer.write(format!("AT{}={},1,{} {}", TIMESW_PATTERN, message.number,
                                       message.event.time, message.event.state));
         }
+
+        Ok(())
     }
 }
 
@@ -610,13 +1075,18 @@ impl Handler<GetOutputCustomSchedule> for RelayActor {
 }
 
 impl Handler<SetOutputCustomSchedule> for RelayActor {
-    type Result = ();
+    type Result = Result<(), OutputError>;
 
     fn handle(&mut self, message: SetOutputCustomSchedule, _: &mut Context<Self>) -> Self::Result {
+        self.check_native_schedule(message.number)?;
+
         if let Some(ref mut line_writer) = self.framed {
+            // The relay expects slashes in dates, as returned by the getter and used for the system time.
             line_writer.write(format!("AT{}={},3,{} {}", TIMESW_PATTERN, message.number,
-                                      message.event.date_time, message.event.state));
+                                      message.event.date_time.replace("-", "/"), message.event.state));
         }
+
+        Ok(())
     }
 }
 
@@ -631,12 +1101,14 @@ impl Handler<ClearOutputCustomSchedule> for RelayActor {
 }
 
 impl Handler<SetOutput> for RelayActor {
-    type Result = ();
+    type Result = Result<(), OutputError>;
 
-    fn handle(&mut self, message: SetOutput, _: &mut Context<Self>) -> Self::Result {
-        if let Some(ref mut line_writer) = self.framed {
-            line_writer.write(format!("AT{}{}={}", STACH_PATTERN, message.number, message.state));
+    fn handle(&mut self, message: SetOutput, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(ActivePulse { handle: Some(handle), .. }) = self.pulses.remove(&message.number) {
+            ctx.cancel_future(handle);
         }
+
+        self.set_output(message.number, message.state, ctx).map(|_| ())
     }
 }
 
@@ -649,12 +1121,15 @@ impl Handler<RegisterForStatus> for RelayActor {
         _ctx: &mut Self::Context,
     ) -> Self::Result {
         let id = self.rng.gen::<usize>();
+        let reported = self.inputs_reported;
 
         client.do_send(RelayStatus {
-            inputs: Some(self.inputs.clone()),
+            inputs: if reported { Some(self.inputs.clone()) } else { None },
             outputs: None,
             connected: true,
             time: None,
+            pulses: None,
+            zones: if reported { Some(zone_statuses(&self.zones, &self.inputs)) } else { None },
         });
 
         self.clients.insert(id, client);
@@ -670,3 +1145,31 @@ impl Handler<UnregisterForStatus> for RelayActor {
         self.clients.remove(&id);
     }
 }
+
+impl Handler<PulseOutput> for RelayActor {
+    type Result = Result<(), OutputError>;
+
+    fn handle(&mut self, message: PulseOutput, ctx: &mut Context<Self>) -> Self::Result {
+        self.start_pulse(message.number, message.pulse, ctx)
+    }
+}
+
+impl Handler<CancelPulse> for RelayActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: CancelPulse, ctx: &mut Context<Self>) -> Self::Result {
+        if self.cancel_pulse(message.number, ctx) {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Handler<GetPulse> for RelayActor {
+    type Result = MessageResult<GetPulse>;
+
+    fn handle(&mut self, message: GetPulse, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.pulse_status(message.number))
+    }
+}
\ No newline at end of file
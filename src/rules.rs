@@ -0,0 +1,423 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::events::{self, EventKind};
+use crate::relay::{OutputError, Pulse, PulseOutput, RegisterForStatus, RelayActor, RelayStatus, SetOutput, UnregisterForStatus};
+use crate::storage;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const RULES_FILE: &str = "rules.json";
+const LOG_SIZE: usize = 100;
+
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct InputState {
+    pub input: usize,
+    pub state: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    Edge { input: usize, state: u32 },
+    Held { input: usize, state: u32, secs: u64 },
+    All {
+        inputs: Vec<InputState>,
+        #[serde(default)]
+        secs: u64,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    TimeWindow {
+        from: NaiveTime,
+        to: NaiveTime,
+        #[serde(default)]
+        days: Vec<u32>,
+    },
+    Input { input: usize, state: u32 },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    SetOutput { output: usize, state: u32 },
+    Pulse {
+        output: usize,
+        #[serde(flatten)]
+        pulse: Pulse,
+    },
+    Notify { message: String },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Rule {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+pub type Rules = HashMap<String, Rule>;
+
+#[derive(Clone, Serialize)]
+pub struct RuleLogEntry {
+    pub time: NaiveDateTime,
+    pub action: Action,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct RuleState {
+    active_since: Option<Instant>,
+    fired: bool,
+    log: VecDeque<RuleLogEntry>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Rules")]
+pub struct GetRules;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum RuleError {
+    EmptyTrigger,
+    InvalidInput { input: usize },
+    InvalidOutput { output: usize },
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), RuleError>")]
+pub struct SetRule {
+    pub name: String,
+    pub rule: Rule,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct DeleteRule {
+    pub name: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<RuleLogEntry>, ()>")]
+pub struct GetRuleLog {
+    pub name: String,
+}
+
+pub struct RulesActor {
+    id: usize,
+    data_dir: String,
+    inputs_number: usize,
+    outputs_number: usize,
+    rules: Rules,
+    states: HashMap<String, RuleState>,
+    inputs: Vec<u32>,
+}
+
+impl Default for RulesActor {
+    fn default() -> Self {
+        RulesActor {
+            id: 0,
+            data_dir: "data".to_string(),
+            inputs_number: 4,
+            outputs_number: 4,
+            rules: HashMap::new(),
+            states: HashMap::new(),
+            inputs: Vec::new(),
+        }
+    }
+}
+
+impl Actor for RulesActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.rules = storage::load(&self.rules_path());
+
+        RelayActor::from_registry().send(RegisterForStatus(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        ctx.run_interval(TICK_INTERVAL, |act, ctx| act.evaluate(None, ctx));
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+
+        Running::Stop
+    }
+}
+
+impl Supervised for RulesActor {}
+
+impl SystemService for RulesActor {}
+
+impl Trigger {
+    fn secs(&self) -> u64 {
+        match *self {
+            Trigger::Edge { .. } => 0,
+            Trigger::Held { secs, .. } | Trigger::All { secs, .. } => secs,
+        }
+    }
+
+    fn is_active(&self, inputs: &[u32]) -> bool {
+        let is = |input: usize, state: u32| inputs.get(input.wrapping_sub(1)) == Some(&state);
+
+        match *self {
+            Trigger::Edge { input, state } | Trigger::Held { input, state, .. } => is(input, state),
+            // An empty list would hold from the start, so it never triggers.
+            Trigger::All { ref inputs, .. } => !inputs.is_empty() && inputs.iter().all(|input| is(input.input, input.state)),
+        }
+    }
+
+    fn inputs(&self) -> Vec<usize> {
+        match *self {
+            Trigger::Edge { input, .. } | Trigger::Held { input, .. } => vec![input],
+            Trigger::All { ref inputs, .. } => inputs.iter().map(|input| input.input).collect(),
+        }
+    }
+}
+
+impl Rule {
+    fn validate(&self, inputs_number: usize, outputs_number: usize) -> Result<(), RuleError> {
+        if let Trigger::All { ref inputs, .. } = self.trigger {
+            if inputs.is_empty() {
+                return Err(RuleError::EmptyTrigger);
+            }
+        }
+
+        let condition_inputs = self.conditions.iter().filter_map(|condition| match *condition {
+            Condition::Input { input, .. } => Some(input),
+            Condition::TimeWindow { .. } => None,
+        });
+
+        if let Some(input) = self.trigger.inputs().into_iter().chain(condition_inputs)
+            .find(|&input| input == 0 || input > inputs_number) {
+            return Err(RuleError::InvalidInput { input });
+        }
+
+        let mut outputs = self.actions.iter().filter_map(|action| match *action {
+            Action::SetOutput { output, .. } | Action::Pulse { output, .. } => Some(output),
+            Action::Notify { .. } => None,
+        });
+
+        if let Some(output) = outputs.find(|&output| output == 0 || output > outputs_number) {
+            return Err(RuleError::InvalidOutput { output });
+        }
+
+        Ok(())
+    }
+}
+
+impl Condition {
+    fn holds(&self, inputs: &[u32], now: NaiveDateTime) -> bool {
+        match *self {
+            Condition::TimeWindow { from, to, ref days } => {
+                let time = now.time();
+                let in_window = if from <= to { from <= time && time < to } else { time >= from || time < to };
+
+                in_window && (days.is_empty() || days.contains(&now.weekday().number_from_monday()))
+            }
+            Condition::Input { input, state } => inputs.get(input.wrapping_sub(1)) == Some(&state),
+        }
+    }
+}
+
+impl RulesActor {
+    pub fn new(data_dir: &str, inputs_number: usize, outputs_number: usize) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            inputs_number,
+            outputs_number,
+            ..RulesActor::default()
+        }
+    }
+
+    fn rules_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, RULES_FILE)
+    }
+
+    fn evaluate(&mut self, previous: Option<&[u32]>, ctx: &mut <Self as Actor>::Context) {
+        let now = Local::now().naive_local();
+        let inputs = &self.inputs;
+        let mut due = Vec::new();
+
+        for (name, rule) in &self.rules {
+            let state = self.states.entry(name.clone()).or_default();
+
+            if !rule.trigger.is_active(inputs) {
+                state.active_since = None;
+                state.fired = false;
+                continue;
+            }
+
+            let edge = previous.map_or(false, |previous| !previous.is_empty() && !rule.trigger.is_active(previous));
+
+            if edge || state.active_since.is_none() {
+                state.active_since = Some(Instant::now());
+                state.fired = false;
+            }
+
+            if !rule.enabled || state.fired {
+                continue;
+            }
+
+            if let Trigger::Edge { .. } = rule.trigger {
+                if !edge {
+                    continue;
+                }
+            }
+
+            let held = state.active_since.map_or(false, |since| since.elapsed() >= Duration::from_secs(rule.trigger.secs()));
+
+            if held && rule.conditions.iter().all(|condition| condition.holds(inputs, now)) {
+                state.fired = true;
+                due.push(name.clone());
+            }
+        }
+
+        for name in due {
+            self.execute(name, ctx);
+        }
+    }
+
+    fn execute(&mut self, name: String, ctx: &mut <Self as Actor>::Context) {
+        println!("Rule {} triggered", name);
+
+        for action in self.rules[&name].actions.clone() {
+            match action {
+                Action::SetOutput { output, state } => {
+                    let request = RelayActor::from_registry().send(SetOutput { number: output, state });
+
+                    self.log_result(name.clone(), action, request, ctx);
+                }
+                Action::Pulse { output, ref pulse } => {
+                    let request = RelayActor::from_registry().send(PulseOutput { number: output, pulse: pulse.clone() });
+
+                    self.log_result(name.clone(), action, request, ctx);
+                }
+                Action::Notify { ref message } => {
+                    events::publish(EventKind::RuleNotification { rule: name.clone(), message: message.clone() });
+                    self.log(&name, action, None);
+                }
+            }
+        }
+    }
+
+    fn log_result<M>(&mut self, name: String, action: Action, request: Request<RelayActor, M>, ctx: &mut <Self as Actor>::Context)
+        where M: Message<Result=Result<(), OutputError>> + Send + 'static, RelayActor: Handler<M> {
+        request.into_actor(self)
+            .map(move |res, act, _| {
+                let error = match res {
+                    Ok(Ok(_)) => None,
+                    Ok(Err(err)) => Some(format!("{:?}", err)),
+                    Err(err) => Some(err.to_string()),
+                };
+
+                act.log(&name, action, error);
+            })
+            .spawn(ctx);
+    }
+
+    fn log(&mut self, name: &str, action: Action, error: Option<String>) {
+        let log = &mut self.states.entry(name.to_string()).or_default().log;
+
+        if log.len() >= LOG_SIZE {
+            log.pop_front();
+        }
+
+        log.push_back(RuleLogEntry {
+            time: Local::now().naive_local(),
+            action,
+            error,
+        });
+    }
+}
+
+impl Handler<RelayStatus> for RulesActor {
+    type Result = ();
+
+    fn handle(&mut self, message: RelayStatus, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(inputs) = message.inputs {
+            let previous = std::mem::replace(&mut self.inputs, inputs);
+
+            self.evaluate(Some(&previous), ctx);
+        }
+    }
+}
+
+impl Handler<GetRules> for RulesActor {
+    type Result = MessageResult<GetRules>;
+
+    fn handle(&mut self, _: GetRules, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.rules.clone())
+    }
+}
+
+impl Handler<SetRule> for RulesActor {
+    type Result = Result<(), RuleError>;
+
+    fn handle(&mut self, message: SetRule, _: &mut Context<Self>) -> Self::Result {
+        message.rule.validate(self.inputs_number, self.outputs_number)?;
+
+        if let Some(state) = self.states.get_mut(&message.name) {
+            state.active_since = None;
+            state.fired = false;
+        }
+
+        self.rules.insert(message.name, message.rule);
+        storage::save(&self.rules_path(), &self.rules);
+
+        Ok(())
+    }
+}
+
+impl Handler<DeleteRule> for RulesActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: DeleteRule, _: &mut Context<Self>) -> Self::Result {
+        self.rules.remove(&message.name).ok_or(())?;
+        self.states.remove(&message.name);
+        storage::save(&self.rules_path(), &self.rules);
+
+        Ok(())
+    }
+}
+
+impl Handler<GetRuleLog> for RulesActor {
+    type Result = Result<Vec<RuleLogEntry>, ()>;
+
+    fn handle(&mut self, message: GetRuleLog, _: &mut Context<Self>) -> Self::Result {
+        if !self.rules.contains_key(&message.name) {
+            return Err(());
+        }
+
+        Ok(self.states.get(&message.name)
+            .map(|state| state.log.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
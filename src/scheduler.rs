@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::calendar::{self, Calendar, Calendars};
+use crate::compiler::{self, Compilation};
+use crate::relay::{CustomEvent, RelayActor, SetOutput, SetOutputCustomSchedule};
+use crate::storage;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const CALENDARS_FILE: &str = "calendars.json";
+const SCHEDULES_FILE: &str = "schedules.json";
+const COMPILATION_FILE: &str = "compilation.json";
+
+
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+pub struct Schedule {
+    pub output: usize,
+    pub time: NaiveTime,
+    pub state: u32,
+    #[serde(default)]
+    pub days: Vec<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+pub type Schedules = HashMap<String, Schedule>;
+
+#[derive(Deserialize)]
+pub struct CustomEventsRequest {
+    pub output: usize,
+    pub time: NaiveTime,
+    pub state: u32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum CustomEventsError {
+    CalendarNotFound,
+    RangeTooLong { max_days: i64 },
+    Interlocked { output: usize },
+}
+
+#[derive(Message)]
+#[rtype(result = "Calendars")]
+pub struct GetCalendars;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetCalendar {
+    pub name: String,
+    pub calendar: Calendar,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct DeleteCalendar {
+    pub name: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<CustomEvent>, CustomEventsError>")]
+pub struct GenerateCustomEvents {
+    pub calendar: String,
+    pub request: CustomEventsRequest,
+}
+
+#[derive(Message)]
+#[rtype(result = "Schedules")]
+pub struct GetSchedules;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetSchedule {
+    pub name: String,
+    pub schedule: Schedule,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
+pub struct DeleteSchedule {
+    pub name: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Compilation>")]
+pub struct GetCompilation;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetCompilation(pub Compilation);
+
+pub struct SchedulerActor {
+    pub outputs_number: usize,
+    pub interlocked: Vec<usize>,
+    pub compile_window: usize,
+    data_dir: String,
+    calendars: Calendars,
+    schedules: Schedules,
+    compilation: Option<Compilation>,
+    last_tick: Option<NaiveDateTime>,
+}
+
+impl Default for SchedulerActor {
+    fn default() -> Self {
+        SchedulerActor {
+            outputs_number: 4,
+            interlocked: Vec::new(),
+            compile_window: 0,
+            data_dir: "data".to_string(),
+            calendars: HashMap::new(),
+            schedules: HashMap::new(),
+            compilation: None,
+            last_tick: None,
+        }
+    }
+}
+
+impl Actor for SchedulerActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.calendars = storage::load(&self.calendars_path());
+        self.schedules = storage::load(&self.schedules_path());
+        self.compilation = storage::load(&self.compilation_path());
+
+        ctx.run_interval(TICK_INTERVAL, |act, _| act.tick());
+    }
+}
+
+impl Supervised for SchedulerActor {}
+
+impl SystemService for SchedulerActor {}
+
+impl Schedule {
+    pub fn runs_on(&self, calendars: &Calendars, date: NaiveDate) -> bool {
+        (self.days.is_empty() || self.days.contains(&date.weekday().number_from_monday()))
+            && calendar::matches(calendars, &self.include, &self.exclude, date)
+    }
+
+    fn is_due(&self, calendars: &Calendars, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        [from.date(), to.date()].iter().any(|&date| {
+            let at = date.and_time(self.time);
+
+            from < at && at <= to && self.runs_on(calendars, date)
+        })
+    }
+}
+
+impl SchedulerActor {
+    pub fn new(data_dir: &str, outputs_number: usize, interlocked: Vec<usize>, compile_window: usize) -> Self {
+        Self {
+            outputs_number,
+            interlocked,
+            compile_window,
+            data_dir: data_dir.to_string(),
+            ..SchedulerActor::default()
+        }
+    }
+
+    fn calendars_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, CALENDARS_FILE)
+    }
+
+    fn schedules_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, SCHEDULES_FILE)
+    }
+
+    fn compilation_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, COMPILATION_FILE)
+    }
+
+    fn tick(&mut self) {
+        let now = Local::now().naive_local();
+
+        if let Some(last_tick) = self.last_tick {
+            for (name, schedule) in &self.schedules {
+                if schedule.is_due(&self.calendars, last_tick, now) {
+                    println!("Schedule {}: output {} = {}", name, schedule.output, schedule.state);
+
+                    RelayActor::from_registry().do_send(SetOutput {
+                        number: schedule.output,
+                        state: schedule.state,
+                    });
+                }
+            }
+
+            if self.compile_window > 0 && last_tick.date() != now.date() {
+                let outputs_number = self.outputs_number;
+                let interlocked = self.interlocked.clone();
+                let compile_window = self.compile_window;
+
+                actix::spawn(async move {
+                    if compiler::compile(outputs_number, &interlocked, compile_window, false).await.is_err() {
+                        println!("Unable to roll compiled schedules forward");
+                    }
+                });
+            }
+        }
+
+        self.last_tick = Some(now);
+    }
+}
+
+impl Handler<GetCalendars> for SchedulerActor {
+    type Result = MessageResult<GetCalendars>;
+
+    fn handle(&mut self, _: GetCalendars, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.calendars.clone())
+    }
+}
+
+impl Handler<SetCalendar> for SchedulerActor {
+    type Result = ();
+
+    fn handle(&mut self, message: SetCalendar, _: &mut Context<Self>) -> Self::Result {
+        self.calendars.insert(message.name, message.calendar);
+        storage::save(&self.calendars_path(), &self.calendars);
+    }
+}
+
+impl Handler<DeleteCalendar> for SchedulerActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: DeleteCalendar, _: &mut Context<Self>) -> Self::Result {
+        self.calendars.remove(&message.name).ok_or(())?;
+        storage::save(&self.calendars_path(), &self.calendars);
+
+        Ok(())
+    }
+}
+
+impl Handler<GenerateCustomEvents> for SchedulerActor {
+    type Result = Result<Vec<CustomEvent>, CustomEventsError>;
+
+    fn handle(&mut self, message: GenerateCustomEvents, _: &mut Context<Self>) -> Self::Result {
+        let calendar = self.calendars.get(&message.calendar).ok_or(CustomEventsError::CalendarNotFound)?;
+        let request = message.request;
+
+        if self.interlocked.contains(&request.output) {
+            return Err(CustomEventsError::Interlocked { output: request.output });
+        }
+
+        if (request.to - request.from).num_days() >= calendar::MAX_RANGE_DAYS {
+            return Err(CustomEventsError::RangeTooLong { max_days: calendar::MAX_RANGE_DAYS });
+        }
+
+        let events = calendar.dates(request.from, request.to)
+            .into_iter()
+            .map(|date| CustomEvent {
+                date_time: date.and_time(request.time).format("%Y-%m-%d %H:%M:%S").to_string(),
+                state: request.state,
+            })
+            .collect::<Vec<CustomEvent>>();
+
+        for event in &events {
+            RelayActor::from_registry().do_send(SetOutputCustomSchedule {
+                number: request.output,
+                event: event.clone(),
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Handler<GetSchedules> for SchedulerActor {
+    type Result = MessageResult<GetSchedules>;
+
+    fn handle(&mut self, _: GetSchedules, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.schedules.clone())
+    }
+}
+
+impl Handler<SetSchedule> for SchedulerActor {
+    type Result = ();
+
+    fn handle(&mut self, message: SetSchedule, _: &mut Context<Self>) -> Self::Result {
+        self.schedules.insert(message.name, message.schedule);
+        storage::save(&self.schedules_path(), &self.schedules);
+    }
+}
+
+impl Handler<DeleteSchedule> for SchedulerActor {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, message: DeleteSchedule, _: &mut Context<Self>) -> Self::Result {
+        self.schedules.remove(&message.name).ok_or(())?;
+        storage::save(&self.schedules_path(), &self.schedules);
+
+        Ok(())
+    }
+}
+
+impl Handler<GetCompilation> for SchedulerActor {
+    type Result = MessageResult<GetCompilation>;
+
+    fn handle(&mut self, _: GetCompilation, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.compilation.clone())
+    }
+}
+
+impl Handler<SetCompilation> for SchedulerActor {
+    type Result = ();
+
+    fn handle(&mut self, SetCompilation(compilation): SetCompilation, _: &mut Context<Self>) -> Self::Result {
+        self.compilation = Some(compilation);
+        storage::save(&self.compilation_path(), &self.compilation);
+    }
+}
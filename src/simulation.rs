@@ -0,0 +1,181 @@
+use actix::prelude::*;
+use chrono::{Duration, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::relay::{GetOutput, GetOutputCustomSchedule, GetOutputDailySchedule, RelayActor};
+use crate::rules::{Action, GetRules, RulesActor};
+use crate::scheduler::{GetCalendars, GetCompilation, GetSchedules, SchedulerActor};
+
+const MAX_RANGE_DAYS: i64 = 366;
+
+
+#[derive(Deserialize)]
+pub struct SimulationQuery {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SimulatedEvent {
+    pub time: NaiveDateTime,
+    pub state: u32,
+    pub source: String,
+    pub changes_state: bool,
+}
+
+#[derive(Serialize)]
+pub struct OutputTimeline {
+    pub output: usize,
+    pub initial_state: Option<u32>,
+    pub final_state: Option<u32>,
+    pub events: Vec<SimulatedEvent>,
+    /// Rules that may also switch the output; they depend on inputs so they cannot be placed on the timeline.
+    pub rules: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Conflict {
+    pub output: usize,
+    pub minute: NaiveDateTime,
+    pub events: Vec<SimulatedEvent>,
+}
+
+#[derive(Serialize)]
+pub struct Simulation {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub outputs: Vec<OutputTimeline>,
+    pub conflicts: Vec<Conflict>,
+}
+
+fn minute_of(time: NaiveDateTime) -> NaiveDateTime {
+    time.with_second(0).and_then(|time| time.with_nanosecond(0)).unwrap_or(time)
+}
+
+fn timeline(output: usize, initial_state: Option<u32>, mut events: Vec<SimulatedEvent>,
+            rules: Vec<String>) -> (OutputTimeline, Vec<Conflict>) {
+    events.sort_by(|a, b| a.time.cmp(&b.time));
+
+    let mut conflicts = Vec::new();
+    let mut state = initial_state;
+    let mut start = 0;
+
+    while start < events.len() {
+        let minute = minute_of(events[start].time);
+        let end = start + events[start..].iter().take_while(|event| minute_of(event.time) == minute).count();
+        let group = &events[start..end];
+
+        if group.iter().any(|event| event.state != group[0].state) {
+            conflicts.push(Conflict { output, minute, events: group.to_vec() });
+        }
+
+        start = end;
+    }
+
+    for event in events.iter_mut() {
+        event.changes_state = state != Some(event.state);
+        state = Some(event.state);
+    }
+
+    (OutputTimeline { output, initial_state, final_state: state, events, rules }, conflicts)
+}
+
+pub async fn simulate(outputs_number: usize, query: SimulationQuery) -> Result<Simulation, ()> {
+    if query.to < query.from || query.to - query.from > Duration::days(MAX_RANGE_DAYS) {
+        return Err(());
+    }
+
+    let relay = RelayActor::from_registry();
+    let scheduler = SchedulerActor::from_registry();
+    let schedules = scheduler.send(GetSchedules).await.map_err(|_| ())?;
+    let calendars = scheduler.send(GetCalendars).await.map_err(|_| ())?;
+    let compilation = scheduler.send(GetCompilation).await.map_err(|_| ())?;
+    let rules = RulesActor::from_registry().send(GetRules).await.map_err(|_| ())?;
+    let in_range = |time: NaiveDateTime| query.from <= time && time <= query.to;
+
+    let mut dates = Vec::new();
+    let mut date = query.from.date();
+
+    while date <= query.to.date() {
+        dates.push(date);
+        date = date + Duration::days(1);
+    }
+
+    let mut simulation = Simulation {
+        from: query.from,
+        to: query.to,
+        outputs: Vec::new(),
+        conflicts: Vec::new(),
+    };
+
+    for output in 1..=outputs_number {
+        let initial_state = relay.send(GetOutput { number: output }).await.ok().and_then(Result::ok);
+        let daily = relay.send(GetOutputDailySchedule { number: output }).await.map_err(|_| ())??;
+        let custom = relay.send(GetOutputCustomSchedule { number: output }).await.map_err(|_| ())??;
+        let compiled = compilation.as_ref().and_then(|compilation| compilation.outputs.get(&output));
+        let mut events = Vec::new();
+
+        // Relay events compiled from gateway schedules are already covered by the schedules themselves.
+        let daily = daily.into_iter()
+            .filter(|event| !compiled.map_or(false, |compiled| compiled.daily.contains(event)));
+        let custom = custom.into_iter()
+            .filter(|event| !compiled.map_or(false, |compiled| compiled.custom.contains(event)));
+
+        for event in daily {
+            if let Ok(time) = NaiveTime::parse_from_str(&event.time, "%H:%M:%S") {
+                events.extend(dates.iter()
+                    .map(|date| date.and_time(time))
+                    .filter(|&time| in_range(time))
+                    .map(|time| SimulatedEvent {
+                        time,
+                        state: event.state,
+                        source: "relay_daily".to_string(),
+                        changes_state: false,
+                    }));
+            }
+        }
+
+        for event in custom {
+            if let Ok(time) = NaiveDateTime::parse_from_str(&event.date_time, "%Y-%m-%d %H:%M:%S") {
+                if in_range(time) {
+                    events.push(SimulatedEvent {
+                        time,
+                        state: event.state,
+                        source: "relay_custom".to_string(),
+                        changes_state: false,
+                    });
+                }
+            }
+        }
+
+        for (name, schedule) in schedules.iter().filter(|(_, schedule)| schedule.output == output) {
+            events.extend(dates.iter()
+                .filter(|&&date| schedule.runs_on(&calendars, date))
+                .map(|date| date.and_time(schedule.time))
+                .filter(|&time| in_range(time))
+                .map(|time| SimulatedEvent {
+                    time,
+                    state: schedule.state,
+                    source: format!("schedule:{}", name),
+                    changes_state: false,
+                }));
+        }
+
+        let mut output_rules = rules.iter()
+            .filter(|(_, rule)| rule.enabled && rule.actions.iter().any(|action| match *action {
+                Action::SetOutput { output: number, .. } | Action::Pulse { output: number, .. } => number == output,
+                Action::Notify { .. } => false,
+            }))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+
+        output_rules.sort();
+
+        let (timeline, conflicts) = timeline(output, initial_state, events, output_rules);
+
+        simulation.outputs.push(timeline);
+        simulation.conflicts.extend(conflicts);
+    }
+
+    Ok(simulation)
+}
@@ -0,0 +1,302 @@
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::alarm::AlarmKind;
+use crate::events::{self, EventKind};
+use crate::relay::{RelayActor, SetOutput};
+
+const CHIRP_ON_MS: u64 = 100;
+const CHIRP_OFF_MS: u64 = 200;
+const TEMPORAL_ON_MS: u64 = 500;
+const TEMPORAL_OFF_MS: u64 = 500;
+const TEMPORAL_PAUSE_MS: u64 = 1500;
+
+
+fn default_cutoff_secs() -> u64 {
+    180
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SirenKind {
+    Siren,
+    Strobe,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SirenConfig {
+    pub output: usize,
+    pub kind: SirenKind,
+    #[serde(default = "default_cutoff_secs")]
+    pub cutoff_secs: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SirenPattern {
+    Continuous,
+    Temporal3,
+    Chirp { count: u32 },
+}
+
+#[derive(Clone, Serialize)]
+pub struct SirenStatus {
+    pub kind: Option<AlarmKind>,
+    pub pattern: Option<SirenPattern>,
+    pub sounding: bool,
+    pub strobing: bool,
+    pub started_at: Option<NaiveDateTime>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartSiren {
+    pub kind: AlarmKind,
+    pub started_at: NaiveDateTime,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Chirp {
+    pub count: u32,
+}
+
+#[derive(Message)]
+#[rtype(result = "SirenStatus")]
+pub struct SilenceSirens;
+
+#[derive(Message)]
+#[rtype(result = "SirenStatus")]
+pub struct ResetSirens;
+
+#[derive(Message)]
+#[rtype(result = "SirenStatus")]
+pub struct GetSirenStatus;
+
+pub struct SirenActor {
+    sirens: Vec<SirenConfig>,
+    kind: Option<AlarmKind>,
+    pattern: Option<SirenPattern>,
+    strobing: bool,
+    started_at: Option<NaiveDateTime>,
+    step_handle: Option<SpawnHandle>,
+    cutoff_handle: Option<SpawnHandle>,
+}
+
+impl Default for SirenActor {
+    fn default() -> Self {
+        SirenActor {
+            sirens: Vec::new(),
+            kind: None,
+            pattern: None,
+            strobing: false,
+            started_at: None,
+            step_handle: None,
+            cutoff_handle: None,
+        }
+    }
+}
+
+impl Actor for SirenActor {
+    type Context = Context<Self>;
+}
+
+impl Supervised for SirenActor {}
+
+impl SystemService for SirenActor {}
+
+impl SirenPattern {
+    pub fn for_alarm(kind: AlarmKind) -> SirenPattern {
+        match kind {
+            AlarmKind::Fire => SirenPattern::Temporal3,
+            _ => SirenPattern::Continuous,
+        }
+    }
+
+    fn steps(self) -> (Vec<(u32, u64)>, bool) {
+        match self {
+            SirenPattern::Continuous => (vec![(1, 0)], false),
+            SirenPattern::Temporal3 => (vec![
+                (1, TEMPORAL_ON_MS), (0, TEMPORAL_OFF_MS),
+                (1, TEMPORAL_ON_MS), (0, TEMPORAL_OFF_MS),
+                (1, TEMPORAL_ON_MS), (0, TEMPORAL_PAUSE_MS),
+            ], true),
+            SirenPattern::Chirp { count } => ((0..count)
+                .flat_map(|_| vec![(1, CHIRP_ON_MS), (0, CHIRP_OFF_MS)])
+                .collect(), false),
+        }
+    }
+}
+
+impl SirenActor {
+    pub fn new(sirens: Vec<SirenConfig>) -> Self {
+        Self {
+            sirens,
+            ..SirenActor::default()
+        }
+    }
+
+    fn outputs(&self, kind: SirenKind) -> Vec<usize> {
+        self.sirens.iter()
+            .filter(|siren| siren.kind == kind)
+            .map(|siren| siren.output)
+            .collect()
+    }
+
+    fn set_outputs(&self, kind: SirenKind, state: u32) {
+        for output in self.outputs(kind) {
+            RelayActor::from_registry().do_send(SetOutput { number: output, state });
+        }
+    }
+
+    fn status(&self) -> SirenStatus {
+        SirenStatus {
+            kind: self.kind,
+            pattern: self.pattern,
+            sounding: self.pattern.is_some(),
+            strobing: self.strobing,
+            started_at: self.started_at,
+        }
+    }
+
+    fn play(&mut self, pattern: SirenPattern, ctx: &mut <Self as Actor>::Context) {
+        self.stop_pattern(ctx);
+        self.pattern = Some(pattern);
+        self.step(0, ctx);
+    }
+
+    fn step(&mut self, index: usize, ctx: &mut <Self as Actor>::Context) {
+        let pattern = match self.pattern {
+            Some(pattern) => pattern,
+            None => return,
+        };
+
+        let (steps, repeat) = pattern.steps();
+
+        if index >= steps.len() && !repeat {
+            self.set_outputs(SirenKind::Siren, 0);
+            self.pattern = None;
+            self.step_handle = None;
+
+            return;
+        }
+
+        let (state, duration_ms) = steps[index % steps.len()];
+
+        self.set_outputs(SirenKind::Siren, state);
+
+        if duration_ms > 0 {
+            let next = if repeat { (index + 1) % steps.len() } else { index + 1 };
+
+            self.step_handle = Some(ctx.run_later(Duration::from_millis(duration_ms), move |act, ctx| act.step(next, ctx)));
+        }
+    }
+
+    fn stop_pattern(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if let Some(handle) = self.step_handle.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if self.pattern.take().is_some() {
+            self.set_outputs(SirenKind::Siren, 0);
+        }
+    }
+
+    fn reset(&mut self, ctx: &mut <Self as Actor>::Context) {
+        self.stop_pattern(ctx);
+
+        if let Some(handle) = self.cutoff_handle.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if self.strobing {
+            self.set_outputs(SirenKind::Strobe, 0);
+            self.strobing = false;
+        }
+
+        self.kind = None;
+        self.started_at = None;
+    }
+}
+
+impl Handler<StartSiren> for SirenActor {
+    type Result = ();
+
+    fn handle(&mut self, message: StartSiren, ctx: &mut Context<Self>) -> Self::Result {
+        let cutoff_secs = self.sirens.iter()
+            .filter(|siren| siren.kind == SirenKind::Siren)
+            .map(|siren| siren.cutoff_secs)
+            .min()
+            .unwrap_or_else(default_cutoff_secs);
+
+        let elapsed_secs = (Local::now().naive_local() - message.started_at).num_seconds().max(0) as u64;
+
+        self.reset(ctx);
+        self.kind = Some(message.kind);
+        self.started_at = Some(message.started_at);
+        self.strobing = true;
+        self.set_outputs(SirenKind::Strobe, 1);
+
+        // An alarm restored after a restart keeps the cutoff counted from when it started.
+        if elapsed_secs >= cutoff_secs {
+            println!("Siren cutoff already passed, strobe only");
+
+            return;
+        }
+
+        self.play(SirenPattern::for_alarm(message.kind), ctx);
+
+        self.cutoff_handle = Some(ctx.run_later(Duration::from_secs(cutoff_secs - elapsed_secs), move |act, ctx| {
+            println!("Siren cutoff after {} s", cutoff_secs);
+
+            act.cutoff_handle = None;
+            act.stop_pattern(ctx);
+            events::publish(EventKind::SirenCutoff { cutoff_secs });
+        }));
+    }
+}
+
+impl Handler<Chirp> for SirenActor {
+    type Result = ();
+
+    fn handle(&mut self, message: Chirp, ctx: &mut Context<Self>) -> Self::Result {
+        if self.kind.is_none() {
+            self.play(SirenPattern::Chirp { count: message.count }, ctx);
+        }
+    }
+}
+
+impl Handler<SilenceSirens> for SirenActor {
+    type Result = MessageResult<SilenceSirens>;
+
+    fn handle(&mut self, _: SilenceSirens, ctx: &mut Context<Self>) -> Self::Result {
+        self.stop_pattern(ctx);
+
+        if let Some(handle) = self.cutoff_handle.take() {
+            ctx.cancel_future(handle);
+        }
+
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<ResetSirens> for SirenActor {
+    type Result = MessageResult<ResetSirens>;
+
+    fn handle(&mut self, _: ResetSirens, ctx: &mut Context<Self>) -> Self::Result {
+        self.reset(ctx);
+
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<GetSirenStatus> for SirenActor {
+    type Result = MessageResult<GetSirenStatus>;
+
+    fn handle(&mut self, _: GetSirenStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.status())
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+
+pub fn data_path(data_dir: &str, file_name: &str) -> PathBuf {
+    Path::new(data_dir).join(file_name)
+}
+
+/// Moves an unreadable file out of the way so the next save does not overwrite what is left of it.
+fn set_aside(path: &Path) {
+    let aside = PathBuf::from(format!("{}.corrupt-{}", path.display(), Local::now().format("%Y%m%d%H%M%S")));
+
+    match fs::rename(path, &aside) {
+        Ok(_) => println!("Moved {} to {}", path.display(), aside.display()),
+        Err(err) => println!("Unable to move {} aside: {}", path.display(), err),
+    }
+}
+
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+            println!("Unable to parse {}: {}", path.display(), err);
+            set_aside(path);
+            T::default()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => T::default(),
+        Err(err) => {
+            println!("Unable to read {}: {}", path.display(), err);
+            set_aside(path);
+            T::default()
+        }
+    }
+}
+
+/// Like `load`, but a file that cannot be read or parsed is reported instead of being replaced by defaults.
+pub fn try_load<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|err| format!("unable to parse {}: {}", path.display(), err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(format!("unable to read {}: {}", path.display(), err)),
+    }
+}
+
+fn write_atomic(path: &Path, content: String) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temp, content))
+        .and_then(|_| fs::rename(&temp, path))
+}
+
+pub fn save<T: Serialize>(path: &Path, value: &T) {
+    if let Err(err) = write_atomic(path, serde_json::to_string_pretty(value).unwrap()) {
+        println!("Unable to write {}: {}", path.display(), err);
+    }
+}
+
+pub fn load_lines<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    match fs::read_to_string(path) {
+        Ok(content) => content.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str(line).map_err(|err| {
+                println!("Skipping invalid line in {}: {}", path.display(), err);
+            }).ok())
+            .collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            println!("Unable to read {}: {}", path.display(), err);
+            Vec::new()
+        }
+    }
+}
+
+pub fn append_line<T: Serialize>(path: &Path, value: &T) {
+    let res = path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::OpenOptions::new().create(true).append(true).open(path))
+        .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(value).unwrap()));
+
+    if let Err(err) = res {
+        println!("Unable to append to {}: {}", path.display(), err);
+    }
+}
+
+pub fn save_lines<'a, T: Serialize + 'a>(path: &Path, values: impl IntoIterator<Item=&'a T>) {
+    let content = values.into_iter()
+        .map(|value| serde_json::to_string(value).unwrap() + "\n")
+        .collect::<String>();
+
+    if let Err(err) = write_atomic(path, content) {
+        println!("Unable to write {}: {}", path.display(), err);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use actix::prelude::*;
+use actix::registry::SystemService;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::Duration;
+
+use crate::storage;
+
+const USERS_FILE: &str = "users.json";
+const SALT_LENGTH: usize = 16;
+const HASH_ROUNDS: usize = 10_000;
+const MIN_PIN_LENGTH: usize = 4;
+const MAX_PIN_LENGTH: usize = 12;
+
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct User {
+    pub salt: String,
+    pub pin_hash: String,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub installer: bool,
+    #[serde(default)]
+    pub duress: bool,
+}
+
+pub type Users = HashMap<String, User>;
+
+#[derive(Clone, Serialize)]
+pub struct UserInfo {
+    pub name: String,
+    pub admin: bool,
+    pub installer: bool,
+    #[serde(skip)]
+    pub duress: bool,
+}
+
+/// A user as listed to administrators, the only place a duress code is identified.
+#[derive(Clone, Serialize)]
+pub struct UserEntry {
+    pub name: String,
+    pub admin: bool,
+    pub installer: bool,
+    pub duress: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UserRequest {
+    pub pin: String,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub installer: bool,
+    #[serde(default)]
+    pub duress: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum PinError {
+    PinRequired,
+    InvalidPin { attempts_left: u32 },
+    LockedOut { retry_after_secs: u64 },
+    NotAdmin,
+    InvalidFormat,
+    PinInUse,
+    NotFound,
+    LastAdmin,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Option<UserInfo>, PinError>")]
+pub struct VerifyPin {
+    pub pin: Option<String>,
+    pub required: bool,
+    pub admin: bool,
+    /// Accepts an installer PIN where an administrator is otherwise required.
+    pub installer: bool,
+    /// Off where a wrong PIN must never lock anyone out, such as raising an emergency.
+    pub count_failures: bool,
+    pub client: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<UserEntry>")]
+pub struct GetUsers;
+
+#[derive(Message)]
+#[rtype(result = "Result<(), PinError>")]
+pub struct SetUser {
+    pub name: String,
+    pub pin: String,
+    pub admin: bool,
+    pub installer: bool,
+    pub duress: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), PinError>")]
+pub struct DeleteUser {
+    pub name: String,
+}
+
+#[derive(Default)]
+struct Attempts {
+    failed: u32,
+    locked_until: Option<Instant>,
+}
+
+pub struct UsersActor {
+    data_dir: String,
+    max_attempts: u32,
+    lockout_secs: u64,
+    users: Users,
+    attempts: HashMap<String, Attempts>,
+}
+
+impl Default for UsersActor {
+    fn default() -> Self {
+        UsersActor {
+            data_dir: "data".to_string(),
+            max_attempts: 5,
+            lockout_secs: 300,
+            users: HashMap::new(),
+            attempts: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for UsersActor {
+    type Context = Context<Self>;
+}
+
+impl Supervised for UsersActor {}
+
+impl SystemService for UsersActor {}
+
+fn hash_pin(salt: &[u8], pin: &str) -> String {
+    let mut digest = Sha256::new().chain(salt).chain(pin.as_bytes()).finalize();
+
+    for _ in 1..HASH_ROUNDS {
+        digest = Sha256::new().chain(salt).chain(&digest).finalize();
+    }
+
+    hex::encode(digest)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl User {
+    fn new(pin: &str, admin: bool, installer: bool, duress: bool) -> Self {
+        let salt = thread_rng().gen::<[u8; SALT_LENGTH]>();
+
+        User {
+            salt: hex::encode(salt),
+            pin_hash: hash_pin(&salt, pin),
+            admin,
+            installer,
+            duress,
+        }
+    }
+
+    fn info(&self, name: &str) -> UserInfo {
+        UserInfo {
+            name: name.to_string(),
+            admin: self.admin,
+            installer: self.installer,
+            duress: self.duress,
+        }
+    }
+
+    fn matches(&self, pin: &str) -> bool {
+        match hex::decode(&self.salt) {
+            Ok(salt) => constant_time_eq(hash_pin(&salt, pin).as_bytes(), self.pin_hash.as_bytes()),
+            Err(_) => false,
+        }
+    }
+}
+
+impl UsersActor {
+    /// Users are loaded up front because an unreadable users file must stop the program
+    /// rather than leave the panel without PINs.
+    pub fn load(data_dir: &str) -> Result<Users, String> {
+        storage::try_load(&storage::data_path(data_dir, USERS_FILE))
+    }
+
+    pub fn new(data_dir: &str, users: Users, max_attempts: u32, lockout_secs: u64) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            max_attempts,
+            lockout_secs,
+            users,
+            ..UsersActor::default()
+        }
+    }
+
+    fn users_path(&self) -> PathBuf {
+        storage::data_path(&self.data_dir, USERS_FILE)
+    }
+
+    fn admins(&self) -> usize {
+        self.users.values().filter(|user| user.admin).count()
+    }
+
+    fn verify(&mut self, pin: Option<String>, required: bool, admin: bool, installer: bool, count_failures: bool, client: String) -> Result<Option<UserInfo>, PinError> {
+        // Until the first user is created the panel behaves as it did before PINs existed.
+        if self.users.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(until) = self.attempts.get(&client).and_then(|attempts| attempts.locked_until) {
+            let now = Instant::now();
+
+            if now < until {
+                return Err(PinError::LockedOut { retry_after_secs: (until - now).as_secs() + 1 });
+            }
+
+            self.attempts.remove(&client);
+        }
+
+        let pin = match pin {
+            Some(pin) => pin,
+            None if required => return Err(PinError::PinRequired),
+            None => return Ok(None),
+        };
+
+        let user = self.users.iter()
+            .find(|(_, user)| user.matches(&pin))
+            .map(|(name, user)| user.info(name));
+
+        match user {
+            Some(user) => {
+                self.attempts.remove(&client);
+
+                if admin && !user.admin && !(installer && user.installer) {
+                    return Err(PinError::NotAdmin);
+                }
+
+                Ok(Some(user))
+            }
+            None if !count_failures => Err(PinError::InvalidPin { attempts_left: self.max_attempts }),
+            None => {
+                let attempts = self.attempts.entry(client.clone()).or_default();
+
+                attempts.failed += 1;
+
+                println!("Invalid PIN entered by {} ({} of {})", client, attempts.failed, self.max_attempts);
+
+                if attempts.failed >= self.max_attempts {
+                    attempts.locked_until = Some(Instant::now() + Duration::from_secs(self.lockout_secs));
+
+                    return Err(PinError::LockedOut { retry_after_secs: self.lockout_secs });
+                }
+
+                Err(PinError::InvalidPin { attempts_left: self.max_attempts - attempts.failed })
+            }
+        }
+    }
+}
+
+impl Handler<VerifyPin> for UsersActor {
+    type Result = Result<Option<UserInfo>, PinError>;
+
+    fn handle(&mut self, message: VerifyPin, _: &mut Context<Self>) -> Self::Result {
+        self.verify(message.pin, message.required, message.admin, message.installer, message.count_failures, message.client)
+    }
+}
+
+impl Handler<GetUsers> for UsersActor {
+    type Result = MessageResult<GetUsers>;
+
+    fn handle(&mut self, _: GetUsers, _: &mut Context<Self>) -> Self::Result {
+        let mut users = self.users.iter()
+            .map(|(name, user)| UserEntry {
+                name: name.clone(),
+                admin: user.admin,
+                installer: user.installer,
+                duress: user.duress,
+            })
+            .collect::<Vec<UserEntry>>();
+
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        MessageResult(users)
+    }
+}
+
+impl Handler<SetUser> for UsersActor {
+    type Result = Result<(), PinError>;
+
+    fn handle(&mut self, message: SetUser, _: &mut Context<Self>) -> Self::Result {
+        let pin = &message.pin;
+
+        if pin.len() < MIN_PIN_LENGTH || pin.len() > MAX_PIN_LENGTH || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err(PinError::InvalidFormat);
+        }
+
+        let taken = self.users.iter().any(|(name, user)| *name != message.name && user.matches(pin));
+
+        if taken {
+            return Err(PinError::PinInUse);
+        }
+
+        // The first user is always an administrator so the panel cannot be locked out of user management.
+        if message.duress && self.users.is_empty() {
+            return Err(PinError::NotAdmin);
+        }
+
+        let admin = (message.admin || self.users.is_empty()) && !message.duress;
+        let installer = message.installer && !message.duress;
+        let demoted = self.users.get(&message.name).map_or(false, |user| user.admin) && !admin;
+
+        if demoted && self.admins() == 1 {
+            return Err(PinError::LastAdmin);
+        }
+
+        self.users.insert(message.name, User::new(pin, admin, installer, message.duress));
+        storage::save(&self.users_path(), &self.users);
+
+        Ok(())
+    }
+}
+
+impl Handler<DeleteUser> for UsersActor {
+    type Result = Result<(), PinError>;
+
+    fn handle(&mut self, message: DeleteUser, _: &mut Context<Self>) -> Self::Result {
+        let user = self.users.get(&message.name).ok_or(PinError::NotFound)?;
+
+        if user.admin && self.admins() == 1 {
+            return Err(PinError::LastAdmin);
+        }
+
+        self.users.remove(&message.name);
+        storage::save(&self.users_path(), &self.users);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use actix::prelude::*;
+use actix::registry::SystemService;
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::alarm::{AlarmActor, AlarmError, SetWalkTest};
+use crate::events::{self, EventKind};
+use crate::relay::{Pulse, PulseOutput, RegisterForStatus, RelayActor, RelayStatus, UnregisterForStatus};
+use crate::zones::{Zone, zone_for};
+
+
+fn default_chirp_ms() -> u64 {
+    200
+}
+
+fn default_timeout_secs() -> u64 {
+    3600
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WalkTestConfig {
+    #[serde(default)]
+    pub output: Option<usize>,
+    #[serde(default = "default_chirp_ms")]
+    pub chirp_ms: u64,
+    /// Ends a forgotten walk test so the panel can be armed again, 0 disables it.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for WalkTestConfig {
+    fn default() -> Self {
+        WalkTestConfig {
+            output: None,
+            chirp_ms: default_chirp_ms(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct WalkTestZone {
+    pub input: usize,
+    pub name: String,
+    pub trips: u32,
+    pub changes: u32,
+    pub last_change: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WalkTestReport {
+    pub active: bool,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub seen: Vec<WalkTestZone>,
+    pub not_seen: Vec<WalkTestZone>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum WalkTestError {
+    AlreadyActive,
+    NotActive,
+    NotStarted,
+    Alarm { reason: AlarmError },
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<WalkTestReport, WalkTestError>")]
+pub struct StartWalkTest;
+
+#[derive(Message)]
+#[rtype(result = "Result<WalkTestReport, WalkTestError>")]
+pub struct StopWalkTest;
+
+#[derive(Message)]
+#[rtype(result = "Result<WalkTestReport, WalkTestError>")]
+pub struct GetWalkTest;
+
+pub struct WalkTestActor {
+    id: usize,
+    config: WalkTestConfig,
+    zones: Vec<Zone>,
+    inputs: Vec<u32>,
+    active: bool,
+    /// Set while the alarm is asked to enter the walk test, so a second start cannot slip in.
+    starting: bool,
+    started_at: Option<NaiveDateTime>,
+    finished_at: Option<NaiveDateTime>,
+    results: Vec<WalkTestZone>,
+    timeout: Option<SpawnHandle>,
+}
+
+impl Default for WalkTestActor {
+    fn default() -> Self {
+        WalkTestActor {
+            id: 0,
+            config: WalkTestConfig::default(),
+            zones: Vec::new(),
+            inputs: Vec::new(),
+            active: false,
+            starting: false,
+            started_at: None,
+            finished_at: None,
+            results: Vec::new(),
+            timeout: None,
+        }
+    }
+}
+
+impl Actor for WalkTestActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        RelayActor::from_registry().send(RegisterForStatus(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+
+        Running::Stop
+    }
+}
+
+impl Supervised for WalkTestActor {}
+
+impl SystemService for WalkTestActor {}
+
+impl WalkTestActor {
+    pub fn new(config: WalkTestConfig, zones: Vec<Zone>) -> Self {
+        Self {
+            config,
+            zones,
+            ..WalkTestActor::default()
+        }
+    }
+
+    fn report(&self) -> Result<WalkTestReport, WalkTestError> {
+        let started_at = self.started_at.ok_or(WalkTestError::NotStarted)?;
+        let (seen, not_seen) = self.results.iter()
+            .cloned()
+            .partition(|zone| zone.changes > 0);
+
+        Ok(WalkTestReport {
+            active: self.active,
+            started_at,
+            finished_at: self.finished_at,
+            seen,
+            not_seen,
+        })
+    }
+
+    fn publish(&self) {
+        if let Ok(report) = self.report() {
+            events::publish(EventKind::WalkTestUpdated { report });
+        }
+    }
+
+    fn start(&mut self, ctx: &mut Context<Self>) {
+        println!("Walk test started");
+
+        if let Some(timeout) = self.timeout.take() {
+            ctx.cancel_future(timeout);
+        }
+
+        if self.config.timeout_secs > 0 {
+            self.timeout = Some(ctx.run_later(Duration::from_secs(self.config.timeout_secs), |act, ctx| {
+                println!("Walk test timed out");
+
+                act.timeout = None;
+                act.stop(ctx);
+            }));
+        }
+
+        self.active = true;
+        self.started_at = Some(Local::now().naive_local());
+        self.finished_at = None;
+        self.results = self.zones.iter()
+            .filter(|zone| zone.input >= 1 && zone.input <= self.inputs.len())
+            .map(|zone| WalkTestZone {
+                input: zone.input,
+                name: zone.name.clone(),
+                trips: 0,
+                changes: 0,
+                last_change: None,
+            })
+            .collect();
+
+        self.publish();
+    }
+
+    fn stop(&mut self, ctx: &mut Context<Self>) {
+        println!("Walk test finished");
+
+        if let Some(timeout) = self.timeout.take() {
+            ctx.cancel_future(timeout);
+        }
+
+        AlarmActor::from_registry().do_send(SetWalkTest { active: false });
+
+        self.active = false;
+        self.finished_at = Some(Local::now().naive_local());
+        self.publish();
+    }
+
+    fn record(&mut self, input: usize, state: u32) {
+        let open = match zone_for(&self.zones, input) {
+            Some(zone) => zone.is_open(state),
+            None => return,
+        };
+
+        let result = match self.results.iter_mut().find(|zone| zone.input == input) {
+            Some(result) => result,
+            None => return,
+        };
+
+        println!("Walk test: {} {}", result.name, if open { "tripped" } else { "restored" });
+
+        result.changes += 1;
+        result.last_change = Some(Local::now().naive_local());
+
+        if open {
+            result.trips += 1;
+
+            if let Some(output) = self.config.output {
+                RelayActor::from_registry().do_send(PulseOutput {
+                    number: output,
+                    pulse: Pulse { duration_ms: self.config.chirp_ms, repeat: 1, interval_ms: 0 },
+                });
+            }
+        }
+
+        self.publish();
+    }
+}
+
+impl Handler<RelayStatus> for WalkTestActor {
+    type Result = ();
+
+    fn handle(&mut self, message: RelayStatus, _: &mut Self::Context) -> Self::Result {
+        if let Some(inputs) = message.inputs {
+            let previous = std::mem::replace(&mut self.inputs, inputs);
+
+            if !self.active || previous.is_empty() {
+                return;
+            }
+
+            let changed = self.inputs.iter()
+                .enumerate()
+                .filter(|&(index, state)| previous.get(index) != Some(state))
+                .map(|(index, &state)| (index + 1, state))
+                .collect::<Vec<(usize, u32)>>();
+
+            for (input, state) in changed {
+                self.record(input, state);
+            }
+        }
+    }
+}
+
+impl Handler<StartWalkTest> for WalkTestActor {
+    type Result = ResponseActFuture<Self, Result<WalkTestReport, WalkTestError>>;
+
+    fn handle(&mut self, _: StartWalkTest, _: &mut Context<Self>) -> Self::Result {
+        if self.active || self.starting {
+            return Box::pin(fut::ready(Err(WalkTestError::AlreadyActive)));
+        }
+
+        self.starting = true;
+
+        Box::pin(AlarmActor::from_registry().send(SetWalkTest { active: true })
+            .into_actor(self)
+            .map(|res, act, ctx| {
+                act.starting = false;
+
+                match res {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(reason)) => return Err(WalkTestError::Alarm { reason }),
+                    Err(_) => return Err(WalkTestError::NotStarted),
+                }
+
+                act.start(ctx);
+                act.report()
+            }))
+    }
+}
+
+impl Handler<StopWalkTest> for WalkTestActor {
+    type Result = Result<WalkTestReport, WalkTestError>;
+
+    fn handle(&mut self, _: StopWalkTest, ctx: &mut Context<Self>) -> Self::Result {
+        if !self.active {
+            return Err(WalkTestError::NotActive);
+        }
+
+        self.stop(ctx);
+        self.report()
+    }
+}
+
+impl Handler<GetWalkTest> for WalkTestActor {
+    type Result = Result<WalkTestReport, WalkTestError>;
+
+    fn handle(&mut self, _: GetWalkTest, _: &mut Context<Self>) -> Self::Result {
+        self.report()
+    }
+}
@@ -8,18 +8,43 @@ use actix_web::{
 use actix_web_actors::ws;
 use actix_web_actors::ws::WebsocketContext;
 use futures_util::task::SpawnExt;
+use serde::Deserialize;
 use serde_json::json;
 
+use crate::ajax::{AjaxActor, GetHubStatus};
+use crate::alarm::{AlarmActor, AlarmEventState, GetAlarmEvents, GetAlarmStatus, RaiseAlarm, RaiseAlarmRequest};
+use crate::auto_arm::{AutoArmActor, PostponeAutoArm};
+use crate::event_store;
+use crate::presence::{GetPresence, PresenceActor};
+use crate::events::{Event, EventBusActor, RegisterForEvents, RegisterForRestrictedEvents, UnregisterForEvents};
 use crate::relay;
+use crate::users::{UsersActor, VerifyPin};
+use crate::walk_test::{GetWalkTest, WalkTestActor};
 use crate::relay::{RegisterForStatus, RelayActor, RelayStatus, UnregisterForStatus, GetInputs};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ClientCommand {
+    RaiseAlarm(RaiseAlarmRequest),
+    PostponeAutoArm {
+        #[serde(default)]
+        secs: Option<u64>,
+        #[serde(default)]
+        pin: Option<String>,
+    },
+    /// Switches the connection to restricted events such as duress, for monitoring stations.
+    Monitor { pin: String },
+}
+
 pub struct ClientWebSocket {
     pub id: usize,
+    pub events_id: usize,
     pub hb: Instant,
+    pub client: String,
 }
 
 impl Actor for ClientWebSocket {
@@ -38,11 +63,81 @@ impl Actor for ClientWebSocket {
             })
             .wait(ctx);
 
+        EventBusActor::from_registry().send(RegisterForEvents(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => act.events_id = res,
+                    _ => ctx.stop(),
+                }
+
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        AlarmActor::from_registry().send(GetAlarmStatus)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(res) = res {
+                    ctx.text(json!({ "alarm": res }).to_string());
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        AlarmActor::from_registry().send(GetAlarmEvents { state: Some(AlarmEventState::Raised) })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(res) = res {
+                    ctx.text(json!({ "alarms": res }).to_string());
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        AjaxActor::from_registry().send(GetHubStatus)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(Some(res)) = res {
+                    ctx.text(json!({ "hub": res }).to_string());
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        PresenceActor::from_registry().send(GetPresence)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(res) = res {
+                    ctx.text(json!({ "presence": res }).to_string());
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
+        WalkTestActor::from_registry().send(GetWalkTest)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(Ok(res)) = res {
+                    if res.active {
+                        ctx.text(json!({ "walk_test": res }).to_string());
+                    }
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+
         self.hb(ctx);
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         RelayActor::from_registry().do_send(UnregisterForStatus(self.id));
+        EventBusActor::from_registry().do_send(UnregisterForEvents(self.events_id));
 
         Running::Stop
     }
@@ -60,7 +155,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientWebSocket {
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Text(text)) => ctx.text(text),
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<ClientCommand>(&text) {
+                Ok(command) => self.handle_command(command, ctx),
+                Err(_) => ctx.text(text),
+            },
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
@@ -72,13 +170,125 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientWebSocket {
 }
 
 impl ClientWebSocket {
-    pub fn new() -> Self {
+    pub fn new(client: String) -> Self {
         Self {
             id: 0,
+            events_id: 0,
             hb: Instant::now(),
+            client,
         }
     }
 
+    fn handle_command(&mut self, command: ClientCommand, ctx: &mut <Self as Actor>::Context) {
+        match command {
+            ClientCommand::RaiseAlarm(request) => {
+                event_store::record("ws_command", None, Some(format!("ws:{}", self.client)), json!({
+                    "command": "raise_alarm",
+                    "kind": request.kind,
+                    "silent": request.silent,
+                }));
+
+                AlarmActor::from_registry().send(RaiseAlarm {
+                    kind: request.kind,
+                    silent: request.silent,
+                    client: format!("ws:{}", self.client),
+                    pin: request.pin,
+                })
+                    .into_actor(self)
+                    .then(|res, act, ctx| {
+                        match res {
+                            Ok(Ok(res)) => ctx.text(json!({ "alarm_raised": res }).to_string()),
+                            Ok(Err(err)) => ctx.text(json!({ "command_error": err }).to_string()),
+                            Err(_) => (),
+                        }
+
+                        fut::ready(())
+                    })
+                    .spawn(ctx);
+            }
+            ClientCommand::Monitor { pin } => {
+                let client = format!("ws:{}", self.client);
+
+                // Failures count against the same address as over REST, so a lockout covers both.
+                UsersActor::from_registry()
+                    .send(VerifyPin { pin: Some(pin), required: true, admin: true, installer: false, count_failures: true, client: self.client.clone() })
+                    .into_actor(self)
+                    .then(move |res, act, ctx| {
+                        match res {
+                            Ok(Ok(user)) => {
+                                event_store::record("ws_command", None, Some(client), json!({
+                                    "command": "monitor",
+                                    "user": user.map(|user| user.name),
+                                }));
+
+                                act.monitor(ctx);
+                            }
+                            Ok(Err(err)) => ctx.text(json!({ "command_error": err }).to_string()),
+                            Err(_) => (),
+                        }
+
+                        fut::ready(())
+                    })
+                    .spawn(ctx);
+            }
+            ClientCommand::PostponeAutoArm { secs, pin } => {
+                let client = format!("ws:{}", self.client);
+
+                UsersActor::from_registry()
+                    .send(VerifyPin { pin, required: true, admin: false, installer: false, count_failures: true, client: self.client.clone() })
+                    .into_actor(self)
+                    .then(move |res, act, ctx| {
+                        match res {
+                            Ok(Ok(user)) => {
+                                event_store::record("ws_command", None, Some(client), json!({
+                                    "command": "postpone_auto_arm",
+                                    "secs": secs,
+                                    "user": user.map(|user| user.name),
+                                }));
+
+                                act.postpone_auto_arm(secs, ctx);
+                            }
+                            Ok(Err(err)) => ctx.text(json!({ "command_error": err }).to_string()),
+                            Err(_) => (),
+                        }
+
+                        fut::ready(())
+                    })
+                    .spawn(ctx);
+            }
+        }
+    }
+
+    fn postpone_auto_arm(&mut self, secs: Option<u64>, ctx: &mut <Self as Actor>::Context) {
+        AutoArmActor::from_registry().send(PostponeAutoArm { secs })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(Ok(res)) => ctx.text(json!({ "auto_arm": res }).to_string()),
+                    Ok(Err(err)) => ctx.text(json!({ "command_error": err }).to_string()),
+                    Err(_) => (),
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
+    fn monitor(&mut self, ctx: &mut <Self as Actor>::Context) {
+        EventBusActor::from_registry().send(RegisterForRestrictedEvents(ctx.address().recipient()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Ok(res) = res {
+                    EventBusActor::from_registry().do_send(UnregisterForEvents(act.events_id));
+                    act.events_id = res;
+                    ctx.text(json!({ "monitoring": true }).to_string());
+                }
+
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
     fn hb(&self, ctx: &mut <Self as Actor>::Context) {
         ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
             if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
@@ -99,4 +309,12 @@ impl Handler<RelayStatus> for ClientWebSocket {
     fn handle(&mut self, message: RelayStatus, ctx: &mut Self::Context) -> Self::Result {
         ctx.text(json!(message).to_string());
     }
+}
+
+impl Handler<Event> for ClientWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, message: Event, ctx: &mut Self::Context) -> Self::Result {
+        ctx.text(json!({ "event": message }).to_string());
+    }
 }
\ No newline at end of file
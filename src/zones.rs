@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+
+fn default_partition() -> u32 {
+    1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneType {
+    Instant,
+    EntryExit,
+    #[serde(rename = "24_hour")]
+    TwentyFourHour,
+    Fire,
+    Tamper,
+    Panic,
+    ChimeOnly,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Zone {
+    pub input: usize,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub zone_type: ZoneType,
+    #[serde(default = "default_partition")]
+    pub partition: u32,
+    #[serde(default)]
+    pub interior: bool,
+    #[serde(default)]
+    pub cross_zones: Vec<usize>,
+    /// For normally closed contacts that report 0 while the zone is open.
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ZoneStatus {
+    pub input: usize,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub zone_type: ZoneType,
+    pub partition: u32,
+    pub open: bool,
+}
+
+impl ZoneType {
+    pub fn is_always_armed(self) -> bool {
+        match self {
+            ZoneType::TwentyFourHour | ZoneType::Fire | ZoneType::Tamper | ZoneType::Panic => true,
+            ZoneType::Instant | ZoneType::EntryExit | ZoneType::ChimeOnly => false,
+        }
+    }
+}
+
+impl Zone {
+    pub fn is_open(&self, state: u32) -> bool {
+        (state != 0) != self.active_low
+    }
+
+    pub fn is_linked_to(&self, other: &Zone) -> bool {
+        self.input != other.input && (self.cross_zones.contains(&other.input) || other.cross_zones.contains(&self.input))
+    }
+}
+
+/// Inputs without a configured zone are not monitored.
+pub fn zone_for(zones: &[Zone], input: usize) -> Option<Zone> {
+    zones.iter()
+        .find(|zone| zone.input == input)
+        .cloned()
+}
+
+pub fn zone_statuses(zones: &[Zone], inputs: &[u32]) -> Vec<ZoneStatus> {
+    zones.iter()
+        .filter_map(|zone| {
+            let &state = inputs.get(zone.input.wrapping_sub(1))?;
+
+            Some(ZoneStatus {
+                input: zone.input,
+                name: zone.name.clone(),
+                zone_type: zone.zone_type,
+                partition: zone.partition,
+                open: zone.is_open(state),
+            })
+        })
+        .collect()
+}